@@ -27,7 +27,8 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             asks: asks_zero_in_between.clone(),
             first_update_id: update_id,
             last_update_id: update_id+1,
-        });
+            event_time: None,
+        }).unwrap();
         update_id += 2;
 
         orderbook.handle_diff(OrderBookDiff {
@@ -35,7 +36,8 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             asks: asks_zero_half.clone(),
             first_update_id: update_id,
             last_update_id: update_id+1,
-        });
+            event_time: None,
+        }).unwrap();
         update_id += 2;
 
         orderbook.handle_diff(OrderBookDiff {
@@ -43,7 +45,8 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             asks: asks_add.clone(),
             first_update_id: update_id,
             last_update_id: update_id+1,
-        });
+            event_time: None,
+        }).unwrap();
         update_id += 2;
     }));
 }
@@ -0,0 +1,37 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use challenge::mock::{MockBinance, Scenario};
+use clap::Parser;
+
+/// Serves a scripted stand-in for the Binance depth endpoints
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// JSON scenario file with snapshots and websocket session scripts
+    #[arg(short, long, required_unless_present = "capture")]
+    scenario: Option<PathBuf>,
+
+    /// Capture file, or directory of capture files, to play back instead of a scenario
+    #[arg(short, long, conflicts_with = "scenario")]
+    capture: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:9000")]
+    addr: SocketAddr,
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let scenario = match (&cli.scenario, &cli.capture) {
+        (_, Some(capture)) => Scenario::from_capture(capture)?,
+        (Some(scenario), None) => Scenario::from_file(scenario)?,
+        (None, None) => unreachable!("clap requires a scenario or a capture"),
+    };
+
+    let mock = MockBinance::bind(scenario, cli.addr).await?;
+    let endpoints = mock.endpoints();
+    println!("Mock Binance listening, REST {} websocket {}", endpoints.rest_url(), endpoints.ws_url());
+
+    mock.wait().await
+}
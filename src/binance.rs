@@ -1,203 +1,566 @@
-use std::io::Error;
+use std::{
+    collections::HashMap,
+    io::Error,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    time::Duration,
+};
 
-use bigdecimal::BigDecimal;
-use challenge::orderbook::OrderBookDepth;
-use futures_util::StreamExt;
-use tokio::{sync::{mpsc, oneshot}, task::JoinHandle};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{sync::{mpsc, oneshot, watch}, task::JoinHandle};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, Message},
+};
+use tracing::{debug, debug_span, error, info, instrument, trace, warn, Instrument, Span};
 
+mod endpoints;
 mod parsers;
+mod replay;
 
-use crate::orderbook::{start_orderbook_manager, OrderBook, OrderbookMessage, Pair};
+pub use endpoints::{BinanceEndpoints, PROFILES};
+pub use replay::{ReplayConfig, ReplaySpeed};
+
+use crate::candles::{Candle, CandleConfig};
+use crate::capture::{Capture, CaptureConfig, RecordKind};
+use crate::history::{clip, read_spill, HistoryConfig, TopOfBook};
+use crate::metrics::{metrics, symbol_label, UNKNOWN_SYMBOL};
+use crate::orderbook::{
+    load_orderbook, tick, BookStatus, InvariantConfig, OrderBook, OrderBookDepth, OrderbookManager, OrderbookMessage, Pair, PersistenceConfig, Tips, VerificationConfig,
+};
 
 type WsError = tokio_tungstenite::tungstenite::Error;
 
+/// How long the server gets to answer our close frame before the socket is dropped.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct BinanceClient {
     tx: mpsc::UnboundedSender<OrderbookMessage>,
+    /// Whether a stream session is currently established.
+    connected: Arc<AtomicBool>,
+    /// Whether the books are fed from a capture, see [`BinanceClient::replay`].
+    replay: bool,
+    /// Set to stop the task feeding the books, see [`BinanceClient::shutdown`].
+    shutdown: watch::Sender<bool>,
 }
 
-const BINANCE_WS: &str = "wss://stream.binance.com:9443/stream?streams=btcusdt@depth/ethusdt@depth";
+/// Settings used by the client to reach Binance and keep the books in sync.
+#[derive(Debug, Clone)]
+pub struct BinanceConfig {
+    pub endpoints: BinanceEndpoints,
+    pub pairs: Vec<Pair>,
+    /// `limit` sent when requesting a depth snapshot.
+    pub snapshot_depth: u32,
+    pub reconnect: ReconnectPolicy,
+    /// When set, raw frames and snapshots are written to disk as they are received.
+    pub capture: Option<CaptureConfig>,
+    /// When set, books are saved periodically and restored on startup.
+    pub persistence: Option<PersistenceConfig>,
+    pub history: HistoryConfig,
+    pub candles: CandleConfig,
+    /// Live books that go this long without a diff are reported as stale.
+    pub stale_after: Duration,
+    /// When set, live books are periodically checked against REST snapshots.
+    pub verification: Option<VerificationConfig>,
+    /// How often books are checked for broken invariants and what happens then.
+    pub invariants: InvariantConfig,
+}
+
+/// Exponential backoff applied between websocket sessions.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Consecutive failed attempts before giving up, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
 
 struct BinancePair {
     symbol: &'static str,
     pair: Pair,
+    base: &'static str,
+    quote: &'static str,
 }
 
 const PAIRS: [BinancePair; 2] = [
     BinancePair {
         symbol: "BTCUSDT",
         pair: Pair::BTCUSDT,
+        base: "BTC",
+        quote: "USDT",
     },
     BinancePair {
         symbol: "ETHUSDT",
         pair: Pair::ETHUSDT,
+        base: "ETH",
+        quote: "USDT",
     },
 ];
 
+/// Snapshot limits accepted by the `/api/v3/depth` endpoint.
+pub const SNAPSHOT_DEPTHS: [u32; 8] = [5, 10, 20, 50, 100, 500, 1000, 5000];
+
+pub fn pair_from_symbol(symbol: &str) -> Option<Pair> {
+    PAIRS.iter().find(|p| p.symbol == symbol).map(|p| p.pair)
+}
+
+pub fn supported_symbols() -> Vec<&'static str> {
+    PAIRS.iter().map(|p| p.symbol).collect()
+}
+
+/// Base and quote asset of `pair`, e.g. `("BTC", "USDT")`.
+pub fn assets_for_pair(pair: Pair) -> Option<(&'static str, &'static str)> {
+    PAIRS.iter().find(|p| p.pair == pair).map(|p| (p.base, p.quote))
+}
+
+fn symbol_for_pair(pair: Pair) -> Result<&'static str, Error> {
+    Ok(PAIRS
+        .iter()
+        .find(|p| p.pair == pair)
+        .ok_or_else(|| Error::other("Unknown pair"))?
+        .symbol)
+}
+
 impl BinanceClient {
-    pub fn new() -> (BinanceClient, JoinHandle<()>) {
+    pub fn new(config: BinanceConfig) -> (BinanceClient, JoinHandle<()>) {
         let (tx, rx) = mpsc::unbounded_channel();
 
+        let connected = Arc::new(AtomicBool::new(false));
+        let (shutdown, shutdown_rx) = watch::channel(false);
         let client = BinanceClient {
             tx: tx.clone(),
+            connected: connected.clone(),
+            replay: false,
+            shutdown,
         };
 
         let handle = tokio::spawn(async move {
-            BinanceClient::start_orderbook_stream(rx, tx).await.unwrap();
+            if let Err(err) = BinanceClient::start_orderbook_stream(config, rx, tx, connected.clone(), shutdown_rx).await {
+                error!(error = %err, "Depth stream stopped");
+                connected.store(false, Ordering::Relaxed);
+            }
         });
 
         (client, handle)
     }
 
-    pub async fn get_tips(&self, pair: Pair) -> Result<((BigDecimal, BigDecimal), (BigDecimal, BigDecimal)), Error> {
+    pub async fn get_tips(&self, pair: Pair) -> Result<Tips, Error> {
         let (resp_tx, resp_rx) = oneshot::channel();
-        self.tx.send(OrderbookMessage::Tips(pair, resp_tx)).or_else(|_| {
-            Err(Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to send message to orderbook manager",
-            ))
-        })?;
+        self.tx.send(OrderbookMessage::Tips(pair, resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
 
-        Ok(resp_rx.await.unwrap().unwrap())
+        resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))?
     }
 
     pub async fn get_bids(&self, pair: Pair) -> Result<OrderBookDepth, Error> {
         let (resp_tx, resp_rx) = oneshot::channel();
-        self.tx.send(OrderbookMessage::Bids(pair, resp_tx)).or_else(|_| {
-            Err(Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to send message to orderbook manager",
-            ))
-        })?;
+        self.tx.send(OrderbookMessage::Bids(pair, resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
 
-        Ok(resp_rx.await.unwrap())
+        resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))?
     }
 
     pub async fn get_asks(&self, pair: Pair) -> Result<OrderBookDepth, Error> {
       let (resp_tx, resp_rx) = oneshot::channel();
-      self.tx.send(OrderbookMessage::Asks(pair, resp_tx)).or_else(|_| {
-          Err(Error::new(
-              std::io::ErrorKind::Other,
-              "Failed to send message to orderbook manager",
-          ))
-      })?;
-
-      Ok(resp_rx.await.unwrap())
+      self.tx.send(OrderbookMessage::Asks(pair, resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
+
+      resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))?
   }
 
-    async fn get_orderbook_snapshot(pair: Pair) -> Result<OrderBook, Error> {
-        let binance_pair: &str = PAIRS
-            .iter()
-            .find(|p| p.pair == pair)
-            .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Unknown pair"))?
-            .symbol;
-        
-        let btc_res = reqwest::Client::new()
-            .get("https://api.binance.com/api/v3/depth")
-            .query(&[("symbol", binance_pair), ("limit", "1000")])
+    /// A copy of the book, both sides as of the same update id.
+    pub async fn get_orderbook(&self, pair: Pair) -> Result<OrderBook, Error> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.tx.send(OrderbookMessage::Book(pair, resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
+
+        resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))?
+    }
+
+    /// Copies of several books taken together, so they reflect the same moment.
+    pub async fn get_orderbooks(&self, pairs: &[Pair]) -> Result<Vec<OrderBook>, Error> {
+        self.get_each_orderbook(pairs).await?.into_iter().collect()
+    }
+
+    /// Like [`BinanceClient::get_orderbooks`], with a result per pair so a book that is
+    /// not ready does not hide the others.
+    pub async fn get_each_orderbook(&self, pairs: &[Pair]) -> Result<Vec<Result<OrderBook, Error>>, Error> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.tx.send(OrderbookMessage::Books(pairs.to_vec(), resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
+
+        resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))
+    }
+
+    /// Top of book changes between `from` and `to` (microseconds since the Unix epoch),
+    /// preceded by the entry in effect at `from`. Spilled entries are read off the
+    /// manager task.
+    pub async fn get_history(&self, pair: Pair, from: u64, to: u64) -> Result<Vec<TopOfBook>, Error> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.tx.send(OrderbookMessage::History(pair, from, to, resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
+
+        let range = resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))??;
+        let Some(spill) = range.spill else {
+            return Ok(range.entries);
+        };
+
+        let spilled = tokio::task::spawn_blocking(move || read_spill(&spill, from, to)).await.map_err(Error::other)??;
+        Ok(clip(spilled.into_iter().chain(range.entries), from, to))
+    }
+
+    /// The latest `limit` mid and microprice bars of `interval`, oldest first.
+    pub async fn get_candles(&self, pair: Pair, interval: Duration, limit: usize) -> Result<Vec<Candle>, Error> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.tx.send(OrderbookMessage::Candles(pair, interval, limit, resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
+
+        resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))?
+    }
+
+    /// Status of every tracked book, ordered by symbol.
+    pub async fn get_status(&self) -> Result<Vec<BookStatus>, Error> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.tx.send(OrderbookMessage::Status(resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
+
+        resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))
+    }
+
+    /// Whether the depth stream is connected, or for a replay, whether records are being fed.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Whether the books come from a replayed capture rather than Binance.
+    pub fn is_replay(&self) -> bool {
+        self.replay
+    }
+
+    /// Stops feeding the books, closing the websocket with a close frame and finishing
+    /// the capture file, then has the manager handle the messages already queued, save the books if persistence asks
+    /// for it, and stop. Requests made afterwards fail.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        self.shutdown.send_replace(true);
+        // The feeding task drops its receiver once it has stopped, or already did if it ended
+        self.shutdown.closed().await;
+
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.tx.send(OrderbookMessage::Shutdown(resp_tx)).map_err(|_| Error::other("Failed to send message to orderbook manager"))?;
+
+        resp_rx.await.map_err(|_| Error::other("Orderbook manager dropped the request"))
+    }
+
+    #[instrument(name = "snapshot_fetch", skip_all, fields(symbol = ?pair, last_update_id))]
+    async fn get_orderbook_snapshot(config: &BinanceConfig, pair: Pair, capture: Option<&Capture>) -> Result<OrderBook, Error> {
+        let binance_pair = symbol_for_pair(pair)?;
+        let limit = config.snapshot_depth.to_string();
+        let timer = metrics().snapshot_fetch_seconds.with_label_values(&[binance_pair]).start_timer();
+
+        let res = reqwest::Client::new()
+            .get(config.endpoints.depth_url())
+            .query(&[("symbol", binance_pair), ("limit", limit.as_str())])
             .send()
-            .await
-            .or_else(|_| {
-                Err(Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to get orderbook",
-                ))
-            })?;
-
-        let body = btc_res.text().await.or_else(|_| {
-            Err(Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to read response body",
-            ))
-        })?;
+            .await.map_err(|_| Error::other("Failed to get orderbook"))?;
 
-        let orderbook = parsers::orderbook_from_binance_json(pair, &body).or_else(|_| {
-            Err(Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to parse orderbook",
-            ))
-        })?;
+        let body = res.text().await.map_err(|_| Error::other("Failed to read response body"))?;
+        timer.observe_duration();
+        if let Some(capture) = capture {
+            capture.record(RecordKind::Snapshot, Some(binance_pair), &body);
+        }
+
+        let orderbook = parsers::orderbook_from_binance_json(pair, &body).map_err(|_| Error::other("Failed to parse orderbook"))?;
+        Span::current().record("last_update_id", orderbook.last_update_id());
+        debug!(bids = orderbook.bids().len(), asks = orderbook.asks().len(), "Fetched snapshot");
 
         Ok(orderbook)
     }
 
-    async fn start_orderbook_stream(rx: mpsc::UnboundedReceiver<OrderbookMessage>, tx: mpsc::UnboundedSender<OrderbookMessage>) -> Result<(), Error> {
-        let (ws_stream, _) = connect_async(BINANCE_WS).await.or_else(|err| {
+    fn stream_url(config: &BinanceConfig) -> Result<String, Error> {
+        let streams = config
+            .pairs
+            .iter()
+            .map(|pair| symbol_for_pair(*pair).map(|symbol| format!("{}@depth", symbol.to_lowercase())))
+            .collect::<Result<Vec<String>, Error>>()?;
+
+        Ok(config.endpoints.stream_url(&streams))
+    }
+
+    /// A manager for the books of `config`, set up as it describes.
+    fn orderbook_manager(config: &BinanceConfig, resync_tx: mpsc::UnboundedSender<Pair>) -> OrderbookManager {
+        let mut manager = OrderbookManager::new(&config.pairs)
+            .with_resync_requests(resync_tx)
+            .with_history(&config.history)
+            .with_candles(&config.candles)
+            .with_stale_after(config.stale_after)
+            .with_invariants(config.invariants.clone());
+        if let Some(persistence) = config.persistence.clone() {
+            manager = manager.with_persistence(persistence);
+        }
+        if let Some(verification) = config.verification.clone() {
+            manager = manager.with_verification(verification);
+        }
+        manager
+    }
+
+    async fn start_orderbook_stream(
+        config: BinanceConfig,
+        rx: mpsc::UnboundedReceiver<OrderbookMessage>,
+        tx: mpsc::UnboundedSender<OrderbookMessage>,
+        connected: Arc<AtomicBool>,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<(), Error> {
+        let (resync_tx, mut resync_rx) = mpsc::unbounded_channel();
+        let manager_handle = BinanceClient::orderbook_manager(&config, resync_tx).spawn(rx);
+        let capture = config.capture.clone().map(Capture::start).transpose()?;
+        let mut warm_books = BinanceClient::load_saved_books(&config);
+
+        let mut delay = config.reconnect.initial_delay;
+        let mut failed_attempts = 0;
+        loop {
+            let session = BinanceClient::run_stream_session(&config, tx.clone(), &mut resync_rx, capture.as_ref(), &mut warm_books, &connected, &mut shutdown).await;
+            connected.store(false, Ordering::Relaxed);
+            if *shutdown.borrow() {
+                info!("Depth stream stopped");
+                finish_capture(capture).await;
+                return Ok(());
+            }
+            match session {
+                Ok(()) => {
+                    info!("Websocket stream closed, reconnecting");
+                    delay = config.reconnect.initial_delay;
+                    failed_attempts = 0;
+                },
+                Err(err) => {
+                    failed_attempts += 1;
+                    warn!(attempt = failed_attempts, error = %err, "Websocket session failed");
+                    if config.reconnect.max_attempts.is_some_and(|max| failed_attempts >= max) {
+                        break;
+                    }
+                },
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {},
+                _ = shutdown.changed() => {
+                    info!("Depth stream stopped");
+                    finish_capture(capture).await;
+                    return Ok(());
+                },
+            }
+            delay = (delay * 2).min(config.reconnect.max_delay);
+        }
+
+        manager_handle.abort();
+        finish_capture(capture).await;
+        Err(Error::other(format!("Giving up after {} failed connection attempts", failed_attempts)))
+    }
+
+    /// Books saved by a previous run that are recent enough to warm start from.
+    fn load_saved_books(config: &BinanceConfig) -> HashMap<Pair, OrderBook> {
+        let Some(persistence) = &config.persistence else {
+            return HashMap::new();
+        };
+
+        let mut books = HashMap::new();
+        for pair in config.pairs.iter() {
+            match load_orderbook(&persistence.dir, *pair, persistence.max_age) {
+                Ok(Some(orderbook)) => {
+                    books.insert(*pair, orderbook);
+                },
+                Ok(None) => {},
+                Err(err) => warn!(symbol = ?pair, error = %err, "Ignoring saved book"),
+            }
+        }
+        books
+    }
+
+    /// Connects to the depth stream and bootstraps every book, from `warm_books` when
+    /// one was saved for the pair and from a REST snapshot otherwise, then serves resync
+    /// requests from the manager and, with verification on, periodically sends it fresh
+    /// snapshots to check the books against. Returns `Ok` once an established stream ends
+    /// or, after closing it, once `shutdown` is set.
+    #[instrument(name = "stream_session", skip_all)]
+    async fn run_stream_session(
+        config: &BinanceConfig,
+        tx: mpsc::UnboundedSender<OrderbookMessage>,
+        resync_rx: &mut mpsc::UnboundedReceiver<Pair>,
+        capture: Option<&Capture>,
+        warm_books: &mut HashMap<Pair, OrderBook>,
+        connected: &AtomicBool,
+        shutdown: &mut watch::Receiver<bool>,
+    ) -> Result<(), Error> {
+        // Every book is rebuilt below, older requests are moot
+        while resync_rx.try_recv().is_ok() {}
+        for pair in config.pairs.iter() {
+            tx.send(OrderbookMessage::Resync(*pair)).map_err(|_| Error::other("Orderbook manager is not running"))?;
+        }
+
+        let url = BinanceClient::stream_url(config)?;
+        let (ws_stream, _) = connect_async(&url).await.map_err(|err| {
             let msg = format!("Failed to connect to websocket: {:?}", err.to_string());
-            Err(Error::new(std::io::ErrorKind::Other, msg))
+            Error::other(msg)
         })?;
-        let (_, read) = ws_stream.split();
+        let (mut write, mut read) = ws_stream.split();
+        connected.store(true, Ordering::Relaxed);
+        info!(url = %url, "Connected to the depth stream");
+        if let Some(capture) = capture {
+            capture.record(RecordKind::Connected, None, &url);
+        }
 
-        let handle = tokio::spawn(async move {
-            read.for_each(|msg| async {
-                handle_ws_message(msg, tx.clone()).await;
-            })
-            .await;
-        });
+        let ws_tx = tx.clone();
+        let ws_capture = capture.cloned();
+        let mut handle = tokio::spawn(
+            async move {
+                while let Some(msg) = read.next().await {
+                    let closed = matches!(msg, Ok(Message::Close(_)));
+                    handle_ws_message(msg, ws_tx.clone(), ws_capture.as_ref()).await;
+                    // Nothing follows a close frame, the socket only waits for the server to hang up
+                    if closed {
+                        break;
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        for pair in config.pairs.iter() {
+            if let Some(orderbook) = warm_books.remove(pair) {
+                info!(symbol = ?pair, last_update_id = orderbook.last_update_id(), "Warm starting from saved book");
+                tx.send(OrderbookMessage::WarmStart(orderbook)).map_err(|_| Error::other("Orderbook manager is not running"))?;
+                continue;
+            }
+            let orderbook = match BinanceClient::get_orderbook_snapshot(config, *pair, capture).await {
+                Ok(orderbook) => orderbook,
+                Err(err) => {
+                    handle.abort();
+                    return Err(err);
+                },
+            };
+            tx.send(OrderbookMessage::Snapshot(orderbook)).map_err(|_| Error::other("Orderbook manager is not running"))?;
+        }
+
+        let mut verify_interval = config
+            .verification
+            .as_ref()
+            .map(|verification| tokio::time::interval_at(tokio::time::Instant::now() + verification.interval, verification.interval));
+        loop {
+            tokio::select! {
+                res = &mut handle => {
+                    res.or_else(|err| {
+                        error!(error = %err, "Websocket reader task failed");
+                        Ok::<(), Error>(())
+                    })?;
+                    return Ok(());
+                },
+                Some(pair) = resync_rx.recv() => {
+                    let orderbook = match BinanceClient::get_orderbook_snapshot(config, pair, capture).await {
+                        Ok(orderbook) => orderbook,
+                        Err(err) => {
+                            handle.abort();
+                            return Err(err);
+                        },
+                    };
+                    tx.send(OrderbookMessage::Snapshot(orderbook)).map_err(|_| Error::other("Orderbook manager is not running"))?;
+                },
+                _ = shutdown.changed() => {
+                    info!("Closing the depth stream");
+                    let close = CloseFrame {
+                        code: CloseCode::Normal,
+                        reason: "Shutting down".into(),
+                    };
+                    if let Err(err) = write.send(Message::Close(Some(close))).await {
+                        warn!(error = %err, "Failed to send close frame");
+                    }
+                    // The reader ends once the server answers with its own close frame
+                    if tokio::time::timeout(CLOSE_TIMEOUT, &mut handle).await.is_err() {
+                        handle.abort();
+                    }
+                    return Ok(());
+                },
+                _ = tick(&mut verify_interval) => {
+                    for pair in config.pairs.iter() {
+                        // A failed check is retried on the next tick, the stream is fine
+                        match BinanceClient::get_orderbook_snapshot(config, *pair, None).await {
+                            Ok(orderbook) => {
+                                tx.send(OrderbookMessage::Verify(orderbook)).map_err(|_| Error::other("Orderbook manager is not running"))?;
+                            },
+                            Err(err) => warn!(symbol = ?pair, error = %err, "Skipping verification"),
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
 
-        let btc_orderbook = BinanceClient::get_orderbook_snapshot(Pair::BTCUSDT).await?;
-        println!("BTC orderbook: {:?}", btc_orderbook);
-        
-        let eth_orderbook = BinanceClient::get_orderbook_snapshot(Pair::ETHUSDT).await?;
+/// Completes the capture file once the stream is over, so a shutdown waiting on the
+/// stream leaves no file without its gzip trailer.
+async fn finish_capture(capture: Option<Capture>) {
+    if let Some(capture) = capture {
+        let _ = tokio::task::spawn_blocking(move || capture.finish()).await;
+    }
+}
 
-        let manager_handle = start_orderbook_manager(btc_orderbook, eth_orderbook, rx);
+/// Parses a combined stream frame and forwards the diff it carries to the manager.
+/// Shared by the live stream and replays so both take the same path.
+fn handle_text_frame(text: &str, ws_tx: &mpsc::UnboundedSender<OrderbookMessage>) {
+    let metrics = metrics();
+    let data: serde_json::Value = match serde_json::from_str(text) {
+        Ok(data) => data,
+        Err(err) => {
+            warn!(error = %err, frame = text, "Failed to parse JSON");
+            metrics.ws_messages_received.with_label_values(&[UNKNOWN_SYMBOL]).inc();
+            metrics.ws_messages_failed.with_label_values(&[UNKNOWN_SYMBOL]).inc();
+            return;
+        }
+    };
+    // Only tracked symbols get their own series, whatever the frame claims
+    let symbol = data["data"]["s"].as_str().and_then(pair_from_symbol).map(symbol_label).unwrap_or_else(|| UNKNOWN_SYMBOL.to_string());
+    let symbol = symbol.as_str();
+    metrics.ws_messages_received.with_label_values(&[symbol]).inc();
+    let _span = debug_span!("ws_frame", symbol).entered();
 
-        handle.await.or_else(|err| {
-            println!("Error in websocket stream: {:?}", err);
-            Ok::<(), Error>(())
-        })?;
-        manager_handle.await.or_else(|err| {
-            println!("Error in orderbook manager: {:?}", err);
-            Ok::<(), Error>(())
-        })?;
-        Ok(())
+    let Some(stream_data) = data["data"].as_object() else {
+        warn!(frame = text, "Invalid stream data");
+        metrics.ws_messages_failed.with_label_values(&[symbol]).inc();
+        return;
+    };
+
+    if let Ok((pair, diff)) = parsers::orderbook_diff_from_binance_json(stream_data) {
+        metrics.ws_messages_parsed.with_label_values(&[symbol]).inc();
+        trace!(first_update_id = diff.first_update_id, last_update_id = diff.last_update_id, "Parsed diff");
+        if ws_tx.send(OrderbookMessage::OrderbookDiff(pair, diff)).is_err() {
+            warn!("Orderbook manager is not running, dropping diff");
+        }
+    } else {
+        warn!(frame = text, "Failed to parse orderbook diff");
+        metrics.ws_messages_failed.with_label_values(&[symbol]).inc();
     }
 }
 
 async fn handle_ws_message(
     msg: Result<Message, WsError>,
     ws_tx: mpsc::UnboundedSender<OrderbookMessage>,
+    capture: Option<&Capture>,
 ) {
     let msg = match msg {
         Ok(msg) => msg,
         Err(e) => {
-            println!("Error receiving message: {:?}", e);
+            warn!(error = %e, "Error receiving message");
             return;
         }
     };
 
     match msg {
         Message::Text(text) => {
-            let data: serde_json::Value = serde_json::from_str(&text).or_else(|err| {
-                Err(Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Failed to parse JSON: {:?}", err)
-                ))
-            }).unwrap();
-
-            let stream_data = data["data"]
-                .as_object()
-                .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Invalid stream Data")).unwrap();
-
-            if let Ok((pair, diff)) = parsers::orderbook_diff_from_binance_json(stream_data) {
-                ws_tx
-                    .send(OrderbookMessage::OrderbookDiff(pair, diff))
-                    .unwrap();
-            } else {
-                println!("Failed to parse orderbook diff: {:?}", text);
+            if let Some(capture) = capture {
+                capture.record(RecordKind::Frame, None, &text);
             }
+            handle_text_frame(&text, &ws_tx);
         }
         Message::Binary(bin) => {
-            println!("Dropping unexpected binary message: {:?}", bin);
+            warn!(len = bin.len(), "Dropping unexpected binary message");
         }
         Message::Ping(ping) => {
-            println!("Ping: {:?}", ping);
+            trace!(?ping, "Ping");
         }
         Message::Pong(pong) => {
-            println!("Pong: {:?}", pong);
+            trace!(?pong, "Pong");
         }
         Message::Close(close) => {
-            println!("Close: {:?}", close);
+            info!(?close, "Websocket closed by the server");
         }
     }
 }
@@ -0,0 +1,111 @@
+use std::io::{Error, ErrorKind};
+
+/// Base URLs of a Binance spot deployment. Paths such as `/api/v3/depth` and
+/// `/stream` are appended by the client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinanceEndpoints {
+    rest_url: String,
+    ws_url: String,
+}
+
+/// Named deployments that can be selected without spelling out URLs.
+pub const PROFILES: [&str; 3] = ["mainnet", "testnet", "us"];
+
+impl BinanceEndpoints {
+    pub fn mainnet() -> BinanceEndpoints {
+        BinanceEndpoints {
+            rest_url: "https://api.binance.com".to_string(),
+            ws_url: "wss://stream.binance.com:9443".to_string(),
+        }
+    }
+
+    pub fn testnet() -> BinanceEndpoints {
+        BinanceEndpoints {
+            rest_url: "https://testnet.binance.vision".to_string(),
+            ws_url: "wss://stream.testnet.binance.vision".to_string(),
+        }
+    }
+
+    pub fn binance_us() -> BinanceEndpoints {
+        BinanceEndpoints {
+            rest_url: "https://api.binance.us".to_string(),
+            ws_url: "wss://stream.binance.us:9443".to_string(),
+        }
+    }
+
+    /// Looks up one of the [`PROFILES`] presets by name.
+    pub fn from_profile(profile: &str) -> Option<BinanceEndpoints> {
+        match profile {
+            "mainnet" => Some(BinanceEndpoints::mainnet()),
+            "testnet" => Some(BinanceEndpoints::testnet()),
+            "us" => Some(BinanceEndpoints::binance_us()),
+            _ => None,
+        }
+    }
+
+    /// Arbitrary base URLs, e.g. `http://127.0.0.1:9000` and `ws://127.0.0.1:9000`
+    /// for a local stand-in. Trailing slashes are ignored.
+    pub fn custom(rest_url: &str, ws_url: &str) -> Result<BinanceEndpoints, Error> {
+        if !rest_url.starts_with("http://") && !rest_url.starts_with("https://") {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("REST URL must use http or https, got {:?}", rest_url)));
+        }
+        if !ws_url.starts_with("ws://") && !ws_url.starts_with("wss://") {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Websocket URL must use ws or wss, got {:?}", ws_url)));
+        }
+
+        Ok(BinanceEndpoints {
+            rest_url: rest_url.trim_end_matches('/').to_string(),
+            ws_url: ws_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub fn rest_url(&self) -> &str {
+        &self.rest_url
+    }
+
+    pub fn ws_url(&self) -> &str {
+        &self.ws_url
+    }
+
+    pub fn depth_url(&self) -> String {
+        format!("{}/api/v3/depth", self.rest_url)
+    }
+
+    /// Combined stream URL subscribing to the given stream names.
+    pub fn stream_url(&self, streams: &[String]) -> String {
+        format!("{}/stream?streams={}", self.ws_url, streams.join("/"))
+    }
+}
+
+impl Default for BinanceEndpoints {
+    fn default() -> BinanceEndpoints {
+        BinanceEndpoints::mainnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinanceEndpoints, PROFILES};
+
+    #[test]
+    fn profiles_resolve() {
+        for profile in PROFILES {
+            assert!(BinanceEndpoints::from_profile(profile).is_some());
+        }
+        assert_eq!(BinanceEndpoints::from_profile("mainnet"), Some(BinanceEndpoints::default()));
+        assert_eq!(BinanceEndpoints::from_profile("devnet"), None);
+    }
+
+    #[test]
+    fn custom_endpoints_allow_plain_schemes() {
+        let endpoints = BinanceEndpoints::custom("http://127.0.0.1:9000/", "ws://127.0.0.1:9000").unwrap();
+        assert_eq!(endpoints.depth_url(), "http://127.0.0.1:9000/api/v3/depth");
+        assert_eq!(
+            endpoints.stream_url(&["btcusdt@depth".to_string(), "ethusdt@depth".to_string()]),
+            "ws://127.0.0.1:9000/stream?streams=btcusdt@depth/ethusdt@depth"
+        );
+
+        assert!(BinanceEndpoints::custom("ws://127.0.0.1:9000", "ws://127.0.0.1:9000").is_err());
+        assert!(BinanceEndpoints::custom("http://127.0.0.1:9000", "http://127.0.0.1:9000").is_err());
+    }
+}
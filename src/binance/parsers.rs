@@ -9,72 +9,56 @@ use crate::orderbook::{OrderBook, OrderBookDepth, OrderBookDiff, Pair};
 use super::PAIRS;
 
 pub fn orderbook_diff_from_binance_json(data: &Map<String, Value>) -> Result<(Pair, OrderBookDiff), Error> {
-  let pair = data["s"]
-      .as_str()
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing pair"))?;
+  let pair = data.get("s")
+      .and_then(Value::as_str)
+      .ok_or_else(|| Error::other("Missing pair"))?;
   let pair = PAIRS
       .iter()
       .find(|p| p.symbol == pair)
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Unknown pair"))?
+      .ok_or_else(|| Error::other("Unknown pair"))?
       .pair;
 
-  let first_update_id = data["U"]
-      .as_i64()
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing firstUpdateId"))?;
-  let last_update_id = data["u"]
-      .as_i64()
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing lastUpdateId"))?;
+  let first_update_id = data.get("U")
+      .and_then(Value::as_i64)
+      .ok_or_else(|| Error::other("Missing firstUpdateId"))?;
+  let last_update_id = data.get("u")
+      .and_then(Value::as_i64)
+      .ok_or_else(|| Error::other("Missing lastUpdateId"))?;
+  // Milliseconds on the wire, a time too far out to count in microseconds is ignored
+  let event_time = data.get("E")
+      .and_then(Value::as_u64)
+      .and_then(|millis| millis.checked_mul(1000));
 
-  let bids = data["b"]
-      .as_array()
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing bids"))?
+  let bids = data.get("b")
+      .and_then(Value::as_array)
+      .ok_or_else(|| Error::other("Missing bids"))?
       .iter()
       .map(|bid| {
           let price = bid[0]
               .as_str()
-              .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing bid price"))?;
-          let price = BigDecimal::from_str(price).or_else(|_| {
-              Err(Error::new(
-                  std::io::ErrorKind::Other,
-                  "Failed to parse bid price",
-              ))
-          })?;
+              .ok_or_else(|| Error::other("Missing bid price"))?;
+          let price = BigDecimal::from_str(price).map_err(|_| Error::other("Failed to parse bid price"))?;
           let quantity = bid[1]
               .as_str()
-              .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing bid quantity"))?;
-          let quantity = BigDecimal::from_str(quantity).or_else(|_| {
-              Err(Error::new(
-                  std::io::ErrorKind::Other,
-                  "Failed to parse bid quantity",
-              ))
-          })?;
+              .ok_or_else(|| Error::other("Missing bid quantity"))?;
+          let quantity = BigDecimal::from_str(quantity).map_err(|_| Error::other("Failed to parse bid quantity"))?;
           Ok((price, quantity))
       })
       .collect::<Result<OrderBookDepth, Error>>()?;
 
-  let asks = data["a"]
-      .as_array()
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing asks"))?
+  let asks = data.get("a")
+      .and_then(Value::as_array)
+      .ok_or_else(|| Error::other("Missing asks"))?
       .iter()
       .map(|ask| {
           let price = ask[0]
               .as_str()
-              .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing ask price"))?;
-          let price = BigDecimal::from_str(price).or_else(|_| {
-              Err(Error::new(
-                  std::io::ErrorKind::Other,
-                  "Failed to parse ask price",
-              ))
-          })?;
+              .ok_or_else(|| Error::other("Missing ask price"))?;
+          let price = BigDecimal::from_str(price).map_err(|_| Error::other("Failed to parse ask price"))?;
           let quantity = ask[1]
               .as_str()
-              .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing ask quantity"))?;
-          let quantity = BigDecimal::from_str(quantity).or_else(|_| {
-              Err(Error::new(
-                  std::io::ErrorKind::Other,
-                  "Failed to parse ask quantity",
-              ))
-          })?;
+              .ok_or_else(|| Error::other("Missing ask quantity"))?;
+          let quantity = BigDecimal::from_str(quantity).map_err(|_| Error::other("Failed to parse ask quantity"))?;
           Ok((price, quantity))
       })
       .collect::<Result<OrderBookDepth, Error>>()?;
@@ -86,72 +70,48 @@ pub fn orderbook_diff_from_binance_json(data: &Map<String, Value>) -> Result<(Pa
           asks,
           first_update_id,
           last_update_id,
+          event_time,
       },
   ))
 }
 
 pub fn orderbook_from_binance_json(pair: Pair, json: &str) -> Result<OrderBook, Error> {
-  let data: serde_json::Value = serde_json::from_str(json).or_else(|_| {
-      Err(Error::new(
-          std::io::ErrorKind::Other,
-          "Failed to parse JSON",
-      ))
-  })?;
+  let data: serde_json::Value = serde_json::from_str(json).map_err(|_| Error::other("Failed to parse JSON"))?;
 
   let last_update_id = data["lastUpdateId"]
       .as_i64()
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing lastUpdateId"))?;
+      .ok_or_else(|| Error::other("Missing lastUpdateId"))?;
 
   let bids = data["bids"]
       .as_array()
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing bids"))?
+      .ok_or_else(|| Error::other("Missing bids"))?
       .iter()
       .map(|bid| {
           let price = bid[0]
               .as_str()
-              .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing bid price"))?;
-          let price = BigDecimal::from_str(price).or_else(|_| {
-              Err(Error::new(
-                  std::io::ErrorKind::Other,
-                  "Failed to parse bid price",
-              ))
-          })?;
+              .ok_or_else(|| Error::other("Missing bid price"))?;
+          let price = BigDecimal::from_str(price).map_err(|_| Error::other("Failed to parse bid price"))?;
           let quantity = bid[1]
               .as_str()
-              .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing bid quantity"))?;
-          let quantity = BigDecimal::from_str(quantity).or_else(|_| {
-              Err(Error::new(
-                  std::io::ErrorKind::Other,
-                  "Failed to parse bid quantity",
-              ))
-          })?;
+              .ok_or_else(|| Error::other("Missing bid quantity"))?;
+          let quantity = BigDecimal::from_str(quantity).map_err(|_| Error::other("Failed to parse bid quantity"))?;
           Ok((price, quantity))
       })
       .collect::<Result<OrderBookDepth, Error>>()?;
 
   let asks = data["asks"]
       .as_array()
-      .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing asks"))?
+      .ok_or_else(|| Error::other("Missing asks"))?
       .iter()
       .map(|ask| {
           let price = ask[0]
               .as_str()
-              .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing ask price"))?;
-          let price = BigDecimal::from_str(price).or_else(|_| {
-              Err(Error::new(
-                  std::io::ErrorKind::Other,
-                  "Failed to parse ask price",
-              ))
-          })?;
+              .ok_or_else(|| Error::other("Missing ask price"))?;
+          let price = BigDecimal::from_str(price).map_err(|_| Error::other("Failed to parse ask price"))?;
           let quantity = ask[1]
               .as_str()
-              .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Missing ask quantity"))?;
-          let quantity = BigDecimal::from_str(quantity).or_else(|_| {
-              Err(Error::new(
-                  std::io::ErrorKind::Other,
-                  "Failed to parse ask quantity",
-              ))
-          })?;
+              .ok_or_else(|| Error::other("Missing ask quantity"))?;
+          let quantity = BigDecimal::from_str(quantity).map_err(|_| Error::other("Failed to parse ask quantity"))?;
           Ok((price, quantity))
       })
       .collect::<Result<OrderBookDepth, Error>>()?;
@@ -0,0 +1,189 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::PathBuf,
+    str::FromStr,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    time::Duration,
+};
+
+use tokio::{sync::{mpsc, watch}, task::JoinHandle, time::Instant};
+use tracing::{info, warn};
+
+use crate::capture::{capture_files, CaptureReader, CaptureRecord, RecordKind};
+use crate::orderbook::{OrderbookMessage, Pair};
+
+use super::{handle_text_frame, pair_from_symbol, parsers, BinanceClient, BinanceConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Records are spaced as they were when captured.
+    Original,
+    /// Records are spaced by their captured gap divided by the factor.
+    Accelerated(f64),
+    /// Records are fed back to back.
+    AsFastAsPossible,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = Error;
+
+    /// Accepts `original`, `max`, or a factor such as `10` or `10x`.
+    fn from_str(s: &str) -> Result<ReplaySpeed, Error> {
+        match s {
+            "original" => Ok(ReplaySpeed::Original),
+            "max" => Ok(ReplaySpeed::AsFastAsPossible),
+            factor => {
+                let factor = factor.strip_suffix('x').unwrap_or(factor).parse::<f64>().ok().filter(|f| f.is_finite() && *f > 0.0);
+                match factor {
+                    Some(factor) => Ok(ReplaySpeed::Accelerated(factor)),
+                    None => Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid replay speed {:?}, expected original, max or a positive factor such as 10x", s),
+                    )),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// A capture file, or a directory whose capture files are replayed in order.
+    pub source: PathBuf,
+    pub speed: ReplaySpeed,
+    /// Pairs and manager settings, as for a live client. Endpoints, reconnects and
+    /// capture are unused, and books are neither saved nor verified.
+    pub binance: BinanceConfig,
+}
+
+impl BinanceClient {
+    /// Builds a client whose books are driven by captured data instead of Binance.
+    /// Frames and snapshots go through the same parsers and manager messages as a live
+    /// session, and resync requests are answered by the snapshots found in the capture.
+    /// History, candles and book status follow the capture time of the records, and
+    /// books never go stale. The handle resolves once every record has been fed, or
+    /// earlier if the client shuts down, and the manager keeps serving the final state
+    /// until it does.
+    pub fn replay(config: ReplayConfig) -> Result<(BinanceClient, JoinHandle<()>), Error> {
+        let files = if config.source.is_dir() {
+            capture_files(&config.source)?
+        } else {
+            vec![config.source.clone()]
+        };
+        if files.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, format!("No capture files in {}", config.source.display())));
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let binance = BinanceConfig {
+            persistence: None,
+            verification: None,
+            ..config.binance
+        };
+        let (resync_tx, _) = mpsc::unbounded_channel();
+        BinanceClient::orderbook_manager(&binance, resync_tx).without_staleness().spawn(rx);
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let (shutdown, mut shutdown_rx) = watch::channel(false);
+        let client = BinanceClient {
+            tx: tx.clone(),
+            connected: connected.clone(),
+            replay: true,
+            shutdown,
+        };
+
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                _ = replay_files(files, binance.pairs, config.speed, tx) => {},
+                _ = shutdown_rx.changed() => info!("Replay stopped"),
+            }
+            connected.store(false, Ordering::Relaxed);
+        });
+
+        Ok((client, handle))
+    }
+}
+
+async fn replay_files(files: Vec<PathBuf>, pairs: Vec<Pair>, speed: ReplaySpeed, tx: mpsc::UnboundedSender<OrderbookMessage>) {
+    let mut clock = None;
+    let mut replayed = 0;
+
+    for file in files {
+        let reader = match CaptureReader::open(&file) {
+            Ok(reader) => reader,
+            Err(err) => {
+                warn!(path = %file.display(), error = %err, "Skipping capture file");
+                continue;
+            }
+        };
+
+        for record in reader {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    // Usually the tail of a file that was not finished cleanly
+                    warn!(path = %file.display(), error = %err, "Stopping replay of capture file");
+                    break;
+                }
+            };
+
+            pace(&mut clock, record.received_at, speed).await;
+            let _ = tx.send(OrderbookMessage::Clock(record.received_at));
+            replay_record(&record, &pairs, &tx);
+            replayed += 1;
+        }
+    }
+
+    info!(records = replayed, "Replay finished");
+}
+
+/// Sleeps until the record is due, measured from the first replayed record.
+async fn pace(clock: &mut Option<(u64, Instant)>, received_at: u64, speed: ReplaySpeed) {
+    let factor = match speed {
+        ReplaySpeed::Original => 1.0,
+        ReplaySpeed::Accelerated(factor) => factor,
+        ReplaySpeed::AsFastAsPossible => return,
+    };
+
+    let (first_received_at, started) = *clock.get_or_insert((received_at, Instant::now()));
+    let offset = Duration::from_micros(received_at.saturating_sub(first_received_at)).div_f64(factor);
+    tokio::time::sleep_until(started + offset).await;
+}
+
+fn replay_record(record: &CaptureRecord, pairs: &[Pair], tx: &mpsc::UnboundedSender<OrderbookMessage>) {
+    match record.kind {
+        RecordKind::Connected => {
+            for pair in pairs.iter() {
+                let _ = tx.send(OrderbookMessage::Resync(*pair));
+            }
+        },
+        RecordKind::Frame => handle_text_frame(&record.data, tx),
+        RecordKind::Snapshot => {
+            let Some(pair) = record.symbol.as_deref().and_then(pair_from_symbol) else {
+                warn!(symbol = ?record.symbol, "Skipping snapshot for unknown symbol");
+                return;
+            };
+            match parsers::orderbook_from_binance_json(pair, &record.data) {
+                Ok(orderbook) => {
+                    let _ = tx.send(OrderbookMessage::Snapshot(orderbook));
+                },
+                Err(err) => warn!(symbol = ?pair, error = %err, "Failed to parse captured snapshot"),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplaySpeed;
+
+    #[test]
+    fn parses_speeds() {
+        assert_eq!("original".parse::<ReplaySpeed>().unwrap(), ReplaySpeed::Original);
+        assert_eq!("max".parse::<ReplaySpeed>().unwrap(), ReplaySpeed::AsFastAsPossible);
+        assert_eq!("10x".parse::<ReplaySpeed>().unwrap(), ReplaySpeed::Accelerated(10.0));
+        assert_eq!("0.5".parse::<ReplaySpeed>().unwrap(), ReplaySpeed::Accelerated(0.5));
+        assert!("0".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::history::TopOfBook;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CandleConfig {
+    /// Bar widths to build, each aligned to the Unix epoch.
+    pub intervals: Vec<Duration>,
+    /// Bars kept per pair and interval.
+    pub capacity: usize,
+}
+
+impl Default for CandleConfig {
+    fn default() -> CandleConfig {
+        CandleConfig {
+            intervals: vec![Duration::from_secs(1), Duration::from_secs(60), Duration::from_secs(300)],
+            capacity: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ohlc {
+    pub open: BigDecimal,
+    pub high: BigDecimal,
+    pub low: BigDecimal,
+    pub close: BigDecimal,
+}
+
+impl Ohlc {
+    fn new(value: &BigDecimal) -> Ohlc {
+        Ohlc {
+            open: value.clone(),
+            high: value.clone(),
+            low: value.clone(),
+            close: value.clone(),
+        }
+    }
+
+    fn update(&mut self, value: &BigDecimal) {
+        if *value > self.high {
+            self.high = value.clone();
+        }
+        if *value < self.low {
+            self.low = value.clone();
+        }
+        self.close = value.clone();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    /// Start of the bar in microseconds since the Unix epoch.
+    pub start: u64,
+    pub mid: Ohlc,
+    pub microprice: Ohlc,
+    /// Top of book changes that went into the bar.
+    pub updates: u64,
+}
+
+struct CandleSeries {
+    interval: Duration,
+    candles: VecDeque<Candle>,
+}
+
+/// Builds mid and microprice bars of one pair from its top of book changes.
+/// Intervals without any change produce no bar.
+pub struct CandleAggregator {
+    capacity: usize,
+    series: Vec<CandleSeries>,
+}
+
+impl CandleAggregator {
+    pub fn new(config: &CandleConfig) -> CandleAggregator {
+        CandleAggregator {
+            capacity: config.capacity.max(1),
+            series: config
+                .intervals
+                .iter()
+                .map(|interval| CandleSeries {
+                    interval: *interval,
+                    candles: VecDeque::new(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn intervals(&self) -> Vec<Duration> {
+        self.series.iter().map(|series| series.interval).collect()
+    }
+
+    pub fn update(&mut self, entry: &TopOfBook) {
+        let microprice = entry.microprice();
+        for series in self.series.iter_mut() {
+            let step = (series.interval.as_micros() as u64).max(1);
+            let start = entry.time - entry.time % step;
+
+            match series.candles.back_mut() {
+                // Changes recorded out of order are folded into the open bar
+                Some(candle) if candle.start >= start => {
+                    candle.mid.update(&entry.mid);
+                    candle.microprice.update(&microprice);
+                    candle.updates += 1;
+                },
+                _ => {
+                    if series.candles.len() >= self.capacity {
+                        series.candles.pop_front();
+                    }
+                    series.candles.push_back(Candle {
+                        start,
+                        mid: Ohlc::new(&entry.mid),
+                        microprice: Ohlc::new(&microprice),
+                        updates: 1,
+                    });
+                },
+            }
+        }
+    }
+
+    /// The latest `limit` bars of `interval`, oldest first. The last one may still be open.
+    /// `None` when the interval is not built.
+    pub fn candles(&self, interval: Duration, limit: usize) -> Option<Vec<Candle>> {
+        let series = self.series.iter().find(|series| series.interval == interval)?;
+        let skip = series.candles.len().saturating_sub(limit);
+        Some(series.candles.iter().skip(skip).cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bigdecimal::BigDecimal;
+
+    use super::{CandleAggregator, CandleConfig};
+    use crate::history::TopOfBook;
+
+    fn entry(time: u64, bid: u32, bid_quantity: u32, ask: u32, ask_quantity: u32) -> TopOfBook {
+        TopOfBook::new(
+            time,
+            &((BigDecimal::from(bid), BigDecimal::from(bid_quantity)), (BigDecimal::from(ask), BigDecimal::from(ask_quantity))),
+        )
+    }
+
+    #[test]
+    fn builds_bars_per_interval() {
+        let mut candles = CandleAggregator::new(&CandleConfig {
+            intervals: vec![Duration::from_secs(1), Duration::from_secs(60)],
+            capacity: 2,
+        });
+        candles.update(&entry(60_100_000, 100, 1, 102, 1));
+        candles.update(&entry(60_500_000, 104, 1, 106, 1));
+        candles.update(&entry(60_900_000, 96, 1, 98, 1));
+        candles.update(&entry(61_200_000, 99, 3, 101, 1));
+        candles.update(&entry(63_000_000, 100, 1, 102, 1));
+
+        let seconds = candles.candles(Duration::from_secs(1), 10).unwrap();
+        // Capacity keeps the last two bars and the idle second has none
+        assert_eq!(seconds.iter().map(|c| c.start).collect::<Vec<u64>>(), vec![61_000_000, 63_000_000]);
+        // Three quarters of the weight on the ask price
+        assert_eq!(seconds[0].microprice.close, BigDecimal::new(1005.into(), 1));
+        assert_eq!(seconds[0].mid.close, BigDecimal::from(100));
+
+        let minutes = candles.candles(Duration::from_secs(60), 10).unwrap();
+        assert_eq!(minutes.len(), 1);
+        assert_eq!(minutes[0].mid.open, BigDecimal::from(101));
+        assert_eq!(minutes[0].mid.high, BigDecimal::from(105));
+        assert_eq!(minutes[0].mid.low, BigDecimal::from(97));
+        assert_eq!(minutes[0].mid.close, BigDecimal::from(101));
+        assert_eq!(minutes[0].updates, 5);
+
+        assert_eq!(candles.candles(Duration::from_secs(1), 1).unwrap()[0].start, 63_000_000);
+        assert!(candles.candles(Duration::from_secs(5), 10).is_none());
+    }
+}
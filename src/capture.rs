@@ -0,0 +1,305 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Error, Lines, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::time::now_micros;
+
+/// How long the writer waits for new records before flushing the current file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    /// A websocket session was opened, `data` holds the stream URL.
+    Connected,
+    /// A websocket text frame, verbatim.
+    Frame,
+    /// A REST depth snapshot body, verbatim.
+    Snapshot,
+}
+
+/// One line of a capture file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Local receive time in microseconds since the Unix epoch.
+    pub received_at: u64,
+    pub kind: RecordKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureConfig {
+    pub dir: PathBuf,
+    /// Uncompressed bytes written before rolling over to a new file.
+    pub max_file_bytes: u64,
+    /// Age after which the current file is closed even if it is not full.
+    pub max_file_age: Duration,
+}
+
+/// Handle used to record raw market data. Records are written to gzip compressed
+/// JSON lines files by a background thread, named after the receive time of their
+/// first record so they sort chronologically. The writer finishes the current file
+/// once [`Capture::finish`] is called or every handle is dropped.
+#[derive(Clone)]
+pub struct Capture {
+    /// `None` asks the writer to finish.
+    tx: mpsc::Sender<Option<CaptureRecord>>,
+    writer: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
+}
+
+impl Capture {
+    pub fn start(config: CaptureConfig) -> Result<Capture, Error> {
+        fs::create_dir_all(&config.dir)?;
+
+        let (tx, rx) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("capture-writer".to_string())
+            .spawn(move || write_records(config, rx))?;
+
+        Ok(Capture {
+            tx,
+            writer: Arc::new(Mutex::new(Some(writer))),
+        })
+    }
+
+    pub fn record(&self, kind: RecordKind, symbol: Option<&str>, data: &str) {
+        let record = CaptureRecord {
+            received_at: now_micros(),
+            kind,
+            symbol: symbol.map(|s| s.to_string()),
+            data: data.to_string(),
+        };
+        if self.tx.send(Some(record)).is_err() {
+            warn!("Capture writer is not running, dropping record");
+        }
+    }
+
+    /// Writes out the records sent so far, finishes the current file and waits for the
+    /// writer to stop. Blocks, and records sent afterwards through any handle are dropped.
+    pub fn finish(&self) {
+        let _ = self.tx.send(None);
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            if writer.join().is_err() {
+                error!("Capture writer panicked");
+            }
+        }
+    }
+}
+
+struct CaptureFile {
+    path: PathBuf,
+    encoder: GzEncoder<File>,
+    written: u64,
+    opened_at: Instant,
+}
+
+impl CaptureFile {
+    fn create(dir: &Path, first_record_at: u64) -> Result<CaptureFile, Error> {
+        // Files opened within the same microsecond get increasing sequence numbers, so names sort in write order
+        let mut sequence = 0;
+        let mut path = dir.join(format!("capture-{:020}-{:04}.jsonl.gz", first_record_at, sequence));
+        while path.exists() {
+            sequence += 1;
+            path = dir.join(format!("capture-{:020}-{:04}.jsonl.gz", first_record_at, sequence));
+        }
+
+        Ok(CaptureFile {
+            encoder: GzEncoder::new(File::create(&path)?, Compression::default()),
+            path,
+            written: 0,
+            opened_at: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, record: &CaptureRecord) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.encoder.write_all(&line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn finish(self) {
+        if let Err(err) = self.encoder.finish() {
+            error!(path = %self.path.display(), error = %err, "Failed to finish capture file");
+        }
+    }
+}
+
+fn write_records(config: CaptureConfig, rx: mpsc::Receiver<Option<CaptureRecord>>) {
+    let mut current: Option<CaptureFile> = None;
+
+    loop {
+        match rx.recv_timeout(FLUSH_INTERVAL) {
+            Ok(Some(record)) => {
+                let full = current
+                    .as_ref()
+                    .is_some_and(|file| file.written >= config.max_file_bytes || file.opened_at.elapsed() >= config.max_file_age);
+                if full {
+                    if let Some(file) = current.take() {
+                        file.finish();
+                    }
+                }
+
+                if current.is_none() {
+                    match CaptureFile::create(&config.dir, record.received_at) {
+                        Ok(file) => current = Some(file),
+                        Err(err) => {
+                            error!(dir = %config.dir.display(), error = %err, "Failed to create capture file");
+                            continue;
+                        }
+                    }
+                }
+
+                if let Some(file) = current.as_mut() {
+                    if let Err(err) = file.write(&record) {
+                        error!(path = %file.path.display(), error = %err, "Failed to write capture record");
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(file) = current.as_mut() {
+                    if let Err(err) = file.encoder.flush() {
+                        error!(path = %file.path.display(), error = %err, "Failed to flush capture file");
+                    }
+                }
+            }
+            Ok(None) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    if let Some(file) = current.take() {
+        file.finish();
+    }
+}
+
+/// Capture files in `dir`, oldest first.
+pub fn capture_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, Error>>()?
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("capture-") && name.ends_with(".jsonl.gz"))
+        })
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Iterates over the records of a single capture file.
+pub struct CaptureReader {
+    lines: Lines<BufReader<MultiGzDecoder<File>>>,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> Result<CaptureReader, Error> {
+        Ok(CaptureReader {
+            lines: BufReader::new(MultiGzDecoder::new(File::open(path)?)).lines(),
+        })
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = Result<CaptureRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(serde_json::from_str(&line).map_err(Error::other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::{capture_files, Capture, CaptureConfig, CaptureReader, RecordKind};
+
+    #[test]
+    fn rotates_and_reads_back() {
+        let dir = std::env::temp_dir().join(format!("challenge-capture-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let capture = Capture::start(CaptureConfig {
+            dir: dir.clone(),
+            max_file_bytes: 200,
+            max_file_age: Duration::from_secs(3600),
+        })
+        .unwrap();
+        capture.record(RecordKind::Connected, None, "ws://127.0.0.1:9000/stream?streams=btcusdt@depth");
+        capture.record(RecordKind::Snapshot, Some("BTCUSDT"), r#"{"lastUpdateId":100,"bids":[],"asks":[]}"#);
+        for i in 0..10 {
+            capture.record(RecordKind::Frame, None, &format!(r#"{{"stream":"btcusdt@depth","data":{{"U":{},"u":{}}}}}"#, i, i));
+        }
+        drop(capture);
+
+        // The writer finishes the last file once the handle is gone
+        let mut files = Vec::new();
+        for _ in 0..100 {
+            files = capture_files(&dir).unwrap();
+            let records = files.iter().flat_map(|file| CaptureReader::open(file).unwrap()).filter(|r| r.is_ok()).count();
+            if records == 12 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(files.len() > 1);
+
+        let records = files
+            .iter()
+            .flat_map(|file| CaptureReader::open(file).unwrap())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 12);
+        assert_eq!(records[0].kind, RecordKind::Connected);
+        assert_eq!(records[1].symbol.as_deref(), Some("BTCUSDT"));
+        assert!(records.windows(2).all(|w| w[0].received_at <= w[1].received_at));
+        assert_eq!(records[11].data, r#"{"stream":"btcusdt@depth","data":{"U":9,"u":9}}"#);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finishes_the_file_when_asked() {
+        let dir = std::env::temp_dir().join(format!("challenge-capture-finish-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let capture = Capture::start(CaptureConfig {
+            dir: dir.clone(),
+            max_file_bytes: 1024 * 1024,
+            max_file_age: Duration::from_secs(3600),
+        })
+        .unwrap();
+        let other = capture.clone();
+        capture.record(RecordKind::Connected, None, "ws://127.0.0.1:9000/stream?streams=btcusdt@depth");
+        capture.finish();
+        // Dropped, the file is already complete
+        other.record(RecordKind::Frame, None, "{}");
+
+        let files = capture_files(&dir).unwrap();
+        assert_eq!(files.len(), 1);
+        let records = CaptureReader::open(&files[0]).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
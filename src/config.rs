@@ -0,0 +1,860 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+use challenge::{
+    binance::{self, BinanceConfig, BinanceEndpoints, ReconnectPolicy, ReplayConfig, ReplaySpeed},
+    candles::CandleConfig,
+    capture::CaptureConfig,
+    history::{self, HistoryConfig},
+    orderbook::{InvariantConfig, InvariantReaction, PersistenceConfig, VerificationConfig, DEFAULT_STALE_AFTER},
+    synthetic::SyntheticPair,
+};
+
+/// Command line flags. Every flag can also be set through its `CHALLENGE_*`
+/// environment variable; flags win over the environment, which wins over the
+/// configuration file.
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "Serves Binance orderbook prices over HTTP")]
+pub struct Cli {
+    /// Path to a TOML configuration file
+    #[arg(short, long, env = "CHALLENGE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address the HTTP server binds to
+    #[arg(long, env = "CHALLENGE_HOST")]
+    pub host: Option<String>,
+
+    /// Port the HTTP server listens on
+    #[arg(long, env = "CHALLENGE_PORT")]
+    pub port: Option<u16>,
+
+    /// Number of HTTP worker threads
+    #[arg(long, env = "CHALLENGE_WORKERS")]
+    pub workers: Option<usize>,
+
+    /// Binance deployment: mainnet, testnet, us or custom
+    #[arg(long, env = "CHALLENGE_BINANCE_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Binance REST base URL, overrides the profile
+    #[arg(long, env = "CHALLENGE_BINANCE_REST_URL")]
+    pub rest_url: Option<String>,
+
+    /// Binance websocket base URL, overrides the profile
+    #[arg(long, env = "CHALLENGE_BINANCE_WS_URL")]
+    pub ws_url: Option<String>,
+
+    /// Comma separated list of symbols to track
+    #[arg(long, env = "CHALLENGE_SYMBOLS", value_delimiter = ',')]
+    pub symbols: Option<Vec<String>>,
+
+    /// Number of levels requested per side in REST snapshots
+    #[arg(long, env = "CHALLENGE_SNAPSHOT_DEPTH")]
+    pub snapshot_depth: Option<u32>,
+
+    /// Milliseconds without a diff after which a live book is stale
+    #[arg(long, env = "CHALLENGE_STALE_AFTER_MS")]
+    pub stale_after_ms: Option<u64>,
+
+    /// Delay before the first reconnection attempt, in milliseconds
+    #[arg(long, env = "CHALLENGE_RECONNECT_INITIAL_DELAY_MS")]
+    pub reconnect_initial_delay_ms: Option<u64>,
+
+    /// Upper bound for the reconnection backoff, in milliseconds
+    #[arg(long, env = "CHALLENGE_RECONNECT_MAX_DELAY_MS")]
+    pub reconnect_max_delay_ms: Option<u64>,
+
+    /// Consecutive failed reconnections before giving up, 0 retries forever
+    #[arg(long, env = "CHALLENGE_RECONNECT_MAX_ATTEMPTS")]
+    pub reconnect_max_attempts: Option<u32>,
+
+    /// Directory raw market data is captured to, enables capturing
+    #[arg(long, env = "CHALLENGE_CAPTURE_DIR")]
+    pub capture_dir: Option<PathBuf>,
+
+    /// Directory books are saved to and warm started from, enables persistence
+    #[arg(long, env = "CHALLENGE_PERSISTENCE_DIR")]
+    pub persistence_dir: Option<PathBuf>,
+
+    /// Seconds between book saves
+    #[arg(long, env = "CHALLENGE_PERSISTENCE_INTERVAL_SECS")]
+    pub persistence_interval_secs: Option<u64>,
+
+    /// Seconds between checks of every book against a REST snapshot, 0 disables them
+    #[arg(long, env = "CHALLENGE_VERIFY_INTERVAL_SECS")]
+    pub verify_interval_secs: Option<u64>,
+
+    /// Top of book changes kept in memory per symbol
+    #[arg(long, env = "CHALLENGE_HISTORY_CAPACITY")]
+    pub history_capacity: Option<usize>,
+
+    /// Directory top of book changes are spilled to once they leave memory
+    #[arg(long, env = "CHALLENGE_HISTORY_SPILL_DIR")]
+    pub history_spill_dir: Option<PathBuf>,
+
+    /// Comma separated candle intervals to build, e.g. 1s,1m,5m
+    #[arg(long, env = "CHALLENGE_CANDLE_INTERVALS", value_delimiter = ',')]
+    pub candle_intervals: Option<Vec<String>>,
+
+    /// Capture file or directory to replay instead of connecting to Binance
+    #[arg(long, env = "CHALLENGE_REPLAY")]
+    pub replay: Option<PathBuf>,
+
+    /// Replay pace: original, max, or a factor such as 10x
+    #[arg(long, env = "CHALLENGE_REPLAY_SPEED")]
+    pub replay_speed: Option<String>,
+
+    /// Minimum level of emitted log lines
+    #[arg(long, env = "CHALLENGE_LOG_LEVEL")]
+    pub log_level: Option<LogLevel>,
+
+    /// Log output format
+    #[arg(long, env = "CHALLENGE_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub binance: BinanceSection,
+    pub reconnect: ReconnectConfig,
+    pub capture: CaptureSection,
+    pub persistence: PersistenceSection,
+    pub verification: VerificationSection,
+    pub invariants: InvariantsSection,
+    pub history: HistorySection,
+    pub candles: CandlesSection,
+    pub synthetic: Vec<SyntheticSection>,
+    pub replay: ReplaySection,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Defaults to the number of physical cores when unset.
+    pub workers: Option<usize>,
+    /// Time given to in-flight requests, and then to the books, to finish on shutdown.
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BinanceSection {
+    /// One of [`binance::PROFILES`], or `custom` when both URLs are given.
+    pub profile: String,
+    pub rest_url: Option<String>,
+    pub ws_url: Option<String>,
+    pub symbols: Vec<String>,
+    pub snapshot_depth: u32,
+    /// A live book that goes this long without a diff is stale.
+    pub stale_after_ms: u64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// 0 retries forever.
+    pub max_attempts: u32,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureSection {
+    /// Capturing is off unless a directory is set.
+    pub dir: Option<PathBuf>,
+    pub max_file_mb: u64,
+    pub rotate_interval_secs: u64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceSection {
+    /// Persistence is off unless a directory is set.
+    pub dir: Option<PathBuf>,
+    pub interval_secs: u64,
+    pub max_age_secs: u64,
+    pub save_on_shutdown: bool,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerificationSection {
+    /// Verification is off while this is 0.
+    pub interval_secs: u64,
+    pub resync_on_mismatch: bool,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InvariantsSection {
+    /// Checks one in this many diffs per book, 0 never does.
+    pub sample_every: u64,
+    /// Checks after every diff, overriding `sample_every`. On by default in debug builds.
+    pub check_every_diff: bool,
+    pub reaction: InvariantReaction,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistorySection {
+    pub capacity: usize,
+    /// Evicted entries are dropped unless a directory is set.
+    pub spill_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CandlesSection {
+    /// Bar widths such as `1s`, `1m` or `5m`.
+    pub intervals: Vec<String>,
+    /// Bars kept per symbol and interval.
+    pub capacity: usize,
+}
+
+/// A pair priced by chaining tracked symbols, e.g. ETH in BTC through ETHUSDT and BTCUSDT.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SyntheticSection {
+    pub base: String,
+    pub quote: String,
+    /// Tracked symbols leading from `base` to `quote`.
+    pub legs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplaySection {
+    /// Replaying is off unless a source is set.
+    pub source: Option<PathBuf>,
+    pub speed: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            workers: None,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for BinanceSection {
+    fn default() -> BinanceSection {
+        BinanceSection {
+            profile: "mainnet".to_string(),
+            rest_url: None,
+            ws_url: None,
+            symbols: vec!["BTCUSDT".to_string(), "ETHUSDT".to_string()],
+            snapshot_depth: 1000,
+            stale_after_ms: DEFAULT_STALE_AFTER.as_millis() as u64,
+        }
+    }
+}
+
+impl Default for InvariantsSection {
+    fn default() -> InvariantsSection {
+        let defaults = InvariantConfig::default();
+        InvariantsSection {
+            sample_every: defaults.sample_every,
+            check_every_diff: defaults.check_every_diff,
+            reaction: defaults.reaction,
+        }
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> ReconnectConfig {
+        ReconnectConfig {
+            initial_delay_ms: 500,
+            max_delay_ms: 30_000,
+            max_attempts: 0,
+        }
+    }
+}
+
+impl Default for CaptureSection {
+    fn default() -> CaptureSection {
+        CaptureSection {
+            dir: None,
+            max_file_mb: 256,
+            rotate_interval_secs: 3600,
+        }
+    }
+}
+
+impl Default for PersistenceSection {
+    fn default() -> PersistenceSection {
+        PersistenceSection {
+            dir: None,
+            interval_secs: 30,
+            max_age_secs: 300,
+            save_on_shutdown: true,
+        }
+    }
+}
+
+impl Default for HistorySection {
+    fn default() -> HistorySection {
+        let defaults = HistoryConfig::default();
+        HistorySection {
+            capacity: defaults.capacity,
+            spill_dir: defaults.spill_dir,
+        }
+    }
+}
+
+impl Default for CandlesSection {
+    fn default() -> CandlesSection {
+        CandlesSection {
+            intervals: vec!["1s".to_string(), "1m".to_string(), "5m".to_string()],
+            capacity: CandleConfig::default().capacity,
+        }
+    }
+}
+
+impl Default for ReplaySection {
+    fn default() -> ReplaySection {
+        ReplaySection {
+            source: None,
+            speed: "original".to_string(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            level: LogLevel::Info,
+            format: LogFormat::Text,
+        }
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+impl Config {
+    /// Builds the configuration from the process arguments, environment and the
+    /// optional configuration file, and validates it.
+    pub fn load() -> Result<Config, Error> {
+        Config::from_cli(Cli::parse())
+    }
+
+    pub fn from_cli(cli: Cli) -> Result<Config, Error> {
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply_overrides(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        let contents = fs::read_to_string(path).map_err(|err| {
+            Error::new(err.kind(), format!("Failed to read config file {}: {}", path.display(), err))
+        })?;
+        Config::from_toml(&contents).map_err(|err| invalid(format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Config, Error> {
+        toml::from_str(contents).map_err(|err| invalid(format!("Invalid configuration: {}", err.message())))
+    }
+
+    fn apply_overrides(&mut self, cli: Cli) {
+        if let Some(host) = cli.host {
+            self.server.host = host;
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if let Some(workers) = cli.workers {
+            self.server.workers = Some(workers);
+        }
+        if let Some(profile) = cli.profile {
+            self.binance.profile = profile;
+        }
+        if let Some(rest_url) = cli.rest_url {
+            self.binance.rest_url = Some(rest_url);
+        }
+        if let Some(ws_url) = cli.ws_url {
+            self.binance.ws_url = Some(ws_url);
+        }
+        if let Some(symbols) = cli.symbols {
+            self.binance.symbols = symbols;
+        }
+        if let Some(snapshot_depth) = cli.snapshot_depth {
+            self.binance.snapshot_depth = snapshot_depth;
+        }
+        if let Some(stale_after_ms) = cli.stale_after_ms {
+            self.binance.stale_after_ms = stale_after_ms;
+        }
+        if let Some(initial_delay_ms) = cli.reconnect_initial_delay_ms {
+            self.reconnect.initial_delay_ms = initial_delay_ms;
+        }
+        if let Some(max_delay_ms) = cli.reconnect_max_delay_ms {
+            self.reconnect.max_delay_ms = max_delay_ms;
+        }
+        if let Some(max_attempts) = cli.reconnect_max_attempts {
+            self.reconnect.max_attempts = max_attempts;
+        }
+        if let Some(dir) = cli.capture_dir {
+            self.capture.dir = Some(dir);
+        }
+        if let Some(dir) = cli.persistence_dir {
+            self.persistence.dir = Some(dir);
+        }
+        if let Some(interval_secs) = cli.persistence_interval_secs {
+            self.persistence.interval_secs = interval_secs;
+        }
+        if let Some(interval_secs) = cli.verify_interval_secs {
+            self.verification.interval_secs = interval_secs;
+        }
+        if let Some(capacity) = cli.history_capacity {
+            self.history.capacity = capacity;
+        }
+        if let Some(dir) = cli.history_spill_dir {
+            self.history.spill_dir = Some(dir);
+        }
+        if let Some(intervals) = cli.candle_intervals {
+            self.candles.intervals = intervals;
+        }
+        if let Some(source) = cli.replay {
+            self.replay.source = Some(source);
+        }
+        if let Some(speed) = cli.replay_speed {
+            self.replay.speed = speed;
+        }
+        if let Some(level) = cli.log_level {
+            self.logging.level = level;
+        }
+        if let Some(format) = cli.log_format {
+            self.logging.format = format;
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.server.host.trim().is_empty() {
+            return Err(invalid("server.host must not be empty".to_string()));
+        }
+        if self.server.workers == Some(0) {
+            return Err(invalid("server.workers must be at least 1".to_string()));
+        }
+        if self.server.shutdown_timeout_secs == 0 {
+            return Err(invalid("server.shutdown_timeout_secs must be greater than 0".to_string()));
+        }
+
+        self.endpoints()?;
+        if self.binance.symbols.is_empty() {
+            return Err(invalid("binance.symbols must list at least one symbol".to_string()));
+        }
+        for (i, symbol) in self.binance.symbols.iter().enumerate() {
+            if binance::pair_from_symbol(symbol).is_none() {
+                return Err(invalid(format!(
+                    "binance.symbols: unsupported symbol {:?} (supported: {})",
+                    symbol,
+                    binance::supported_symbols().join(", ")
+                )));
+            }
+            if self.binance.symbols[..i].contains(symbol) {
+                return Err(invalid(format!("binance.symbols: {:?} is listed more than once", symbol)));
+            }
+        }
+        if !binance::SNAPSHOT_DEPTHS.contains(&self.binance.snapshot_depth) {
+            let allowed = binance::SNAPSHOT_DEPTHS.iter().map(|d| d.to_string()).collect::<Vec<String>>();
+            return Err(invalid(format!(
+                "binance.snapshot_depth must be one of {}, got {}",
+                allowed.join(", "),
+                self.binance.snapshot_depth
+            )));
+        }
+        if self.binance.stale_after_ms == 0 {
+            return Err(invalid("binance.stale_after_ms must be greater than 0".to_string()));
+        }
+
+        if self.reconnect.initial_delay_ms == 0 {
+            return Err(invalid("reconnect.initial_delay_ms must be greater than 0".to_string()));
+        }
+        if self.reconnect.max_delay_ms < self.reconnect.initial_delay_ms {
+            return Err(invalid("reconnect.max_delay_ms must not be lower than reconnect.initial_delay_ms".to_string()));
+        }
+
+        if self.capture.max_file_mb == 0 {
+            return Err(invalid("capture.max_file_mb must be greater than 0".to_string()));
+        }
+        if self.capture.rotate_interval_secs == 0 {
+            return Err(invalid("capture.rotate_interval_secs must be greater than 0".to_string()));
+        }
+
+        if self.persistence.interval_secs == 0 {
+            return Err(invalid("persistence.interval_secs must be greater than 0".to_string()));
+        }
+
+        if self.history.capacity == 0 {
+            return Err(invalid("history.capacity must be greater than 0".to_string()));
+        }
+
+        for interval in self.candles.intervals.iter() {
+            history::parse_interval(interval).map_err(|err| invalid(format!("candles.intervals: {}", err)))?;
+        }
+        if self.candles.capacity == 0 {
+            return Err(invalid("candles.capacity must be greater than 0".to_string()));
+        }
+
+        for (i, synthetic) in self.synthetic.iter().enumerate() {
+            let pair = synthetic_pair(synthetic).map_err(|err| invalid(format!("synthetic: {}", err)))?;
+            if let Some(leg) = synthetic.legs.iter().find(|leg| !self.binance.symbols.contains(leg)) {
+                return Err(invalid(format!("synthetic: {} uses {:?}, which is not in binance.symbols", pair.symbol(), leg)));
+            }
+            if binance::pair_from_symbol(pair.symbol()).is_some() {
+                return Err(invalid(format!("synthetic: {} is a Binance symbol", pair.symbol())));
+            }
+            if self.synthetic[..i].iter().any(|other| other.base == synthetic.base && other.quote == synthetic.quote) {
+                return Err(invalid(format!("synthetic: {} is defined more than once", pair.symbol())));
+            }
+        }
+
+        self.replay.speed.parse::<ReplaySpeed>().map_err(|err| invalid(format!("replay.speed: {}", err)))?;
+        if let Some(source) = &self.replay.source {
+            if !source.exists() {
+                return Err(invalid(format!("replay.source: {} does not exist", source.display())));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn endpoints(&self) -> Result<BinanceEndpoints, Error> {
+        let preset = match self.binance.profile.as_str() {
+            "custom" => None,
+            profile => Some(BinanceEndpoints::from_profile(profile).ok_or_else(|| {
+                invalid(format!(
+                    "binance.profile: unknown profile {:?} (expected {} or custom)",
+                    profile,
+                    binance::PROFILES.join(", ")
+                ))
+            })?),
+        };
+
+        let rest_url = match (&self.binance.rest_url, &preset) {
+            (Some(url), _) => url.as_str(),
+            (None, Some(preset)) => preset.rest_url(),
+            (None, None) => return Err(invalid("binance.rest_url is required with the custom profile".to_string())),
+        };
+        let ws_url = match (&self.binance.ws_url, &preset) {
+            (Some(url), _) => url.as_str(),
+            (None, Some(preset)) => preset.ws_url(),
+            (None, None) => return Err(invalid("binance.ws_url is required with the custom profile".to_string())),
+        };
+
+        BinanceEndpoints::custom(rest_url, ws_url).map_err(|err| invalid(format!("binance: {}", err)))
+    }
+
+    /// Replay settings when a replay source is configured.
+    pub fn replay_config(&self) -> Option<ReplayConfig> {
+        self.replay.source.as_ref().map(|source| ReplayConfig {
+            source: source.clone(),
+            speed: self.replay.speed.parse().expect("configuration is validated on load"),
+            binance: self.binance_config(),
+        })
+    }
+
+    pub fn synthetic_pairs(&self) -> Vec<SyntheticPair> {
+        self.synthetic
+            .iter()
+            .map(|synthetic| synthetic_pair(synthetic).expect("configuration is validated on load"))
+            .collect()
+    }
+
+    pub fn binance_config(&self) -> BinanceConfig {
+        BinanceConfig {
+            endpoints: self.endpoints().expect("configuration is validated on load"),
+            pairs: self
+                .binance
+                .symbols
+                .iter()
+                .filter_map(|symbol| binance::pair_from_symbol(symbol))
+                .collect(),
+            snapshot_depth: self.binance.snapshot_depth,
+            reconnect: ReconnectPolicy {
+                initial_delay: Duration::from_millis(self.reconnect.initial_delay_ms),
+                max_delay: Duration::from_millis(self.reconnect.max_delay_ms),
+                max_attempts: match self.reconnect.max_attempts {
+                    0 => None,
+                    max => Some(max),
+                },
+            },
+            capture: self.capture.dir.as_ref().map(|dir| CaptureConfig {
+                dir: dir.clone(),
+                max_file_bytes: self.capture.max_file_mb * 1024 * 1024,
+                max_file_age: Duration::from_secs(self.capture.rotate_interval_secs),
+            }),
+            persistence: self.persistence.dir.as_ref().map(|dir| PersistenceConfig {
+                dir: dir.clone(),
+                interval: Duration::from_secs(self.persistence.interval_secs),
+                max_age: Duration::from_secs(self.persistence.max_age_secs),
+                save_on_shutdown: self.persistence.save_on_shutdown,
+            }),
+            history: HistoryConfig {
+                capacity: self.history.capacity,
+                spill_dir: self.history.spill_dir.clone(),
+            },
+            candles: CandleConfig {
+                intervals: self
+                    .candles
+                    .intervals
+                    .iter()
+                    .map(|interval| history::parse_interval(interval).expect("configuration is validated on load"))
+                    .collect(),
+                capacity: self.candles.capacity,
+            },
+            stale_after: Duration::from_millis(self.binance.stale_after_ms),
+            verification: match self.verification.interval_secs {
+                0 => None,
+                interval_secs => Some(VerificationConfig {
+                    interval: Duration::from_secs(interval_secs),
+                    resync_on_mismatch: self.verification.resync_on_mismatch,
+                }),
+            },
+            invariants: InvariantConfig {
+                sample_every: self.invariants.sample_every,
+                check_every_diff: self.invariants.check_every_diff,
+                reaction: self.invariants.reaction,
+            },
+        }
+    }
+}
+
+fn synthetic_pair(section: &SyntheticSection) -> Result<SyntheticPair, Error> {
+    let legs = section
+        .legs
+        .iter()
+        .map(|symbol| binance::pair_from_symbol(symbol).ok_or_else(|| invalid(format!("unsupported symbol {:?}", symbol))))
+        .collect::<Result<Vec<_>, Error>>()?;
+    SyntheticPair::new(&section.base, &section.quote, &legs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Cli, Config, LogFormat};
+    use challenge::candles::CandleConfig;
+    use challenge::binance::BinanceEndpoints;
+    use challenge::orderbook::{InvariantReaction, Pair};
+
+    #[test]
+    fn defaults_are_valid() {
+        let config = Config::default();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.binance_config().pairs, vec![Pair::BTCUSDT, Pair::ETHUSDT]);
+        assert_eq!(config.binance_config().reconnect.max_attempts, None);
+        assert_eq!(config.binance_config().capture, None);
+        assert_eq!(config.binance_config().persistence, None);
+        assert_eq!(config.binance_config().verification, None);
+        assert_eq!(config.binance_config().candles, CandleConfig::default());
+    }
+
+    #[test]
+    fn example_file_matches_defaults() {
+        let config = Config::from_toml(include_str!("../config.example.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn partial_file_keeps_defaults() {
+        let config = Config::from_toml(
+            r#"
+            [server]
+            port = 9000
+
+            [binance]
+            symbols = ["ETHUSDT"]
+            snapshot_depth = 500
+            stale_after_ms = 5000
+
+            [verification]
+            interval_secs = 60
+
+            [invariants]
+            reaction = "resync"
+
+            [logging]
+            format = "json"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.server.host, "127.0.0.1");
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.binance.snapshot_depth, 500);
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert_eq!(config.binance_config().pairs, vec![Pair::ETHUSDT]);
+        assert_eq!(config.binance_config().stale_after, Duration::from_secs(5));
+        assert_eq!(config.binance_config().verification.unwrap().interval, Duration::from_secs(60));
+        assert_eq!(config.binance_config().invariants.sample_every, 100);
+        assert_eq!(config.binance_config().invariants.check_every_diff, cfg!(debug_assertions));
+        assert_eq!(config.binance_config().invariants.reaction, InvariantReaction::Resync);
+    }
+
+    #[test]
+    fn endpoints_from_profile_and_overrides() {
+        let config = Config::from_toml("[binance]\nprofile = \"testnet\"\n").unwrap();
+        assert_eq!(config.binance_config().endpoints, BinanceEndpoints::testnet());
+
+        let config = Config::from_toml(
+            r#"
+            [binance]
+            profile = "custom"
+            rest_url = "http://127.0.0.1:9000"
+            ws_url = "ws://127.0.0.1:9000/"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.binance_config().endpoints.ws_url(), "ws://127.0.0.1:9000");
+
+        // A single URL can be swapped while keeping the rest of the preset
+        let config = Config::from_toml("[binance]\nprofile = \"us\"\nws_url = \"ws://127.0.0.1:9000\"\n").unwrap();
+        let endpoints = config.binance_config().endpoints;
+        assert_eq!(endpoints.rest_url(), BinanceEndpoints::binance_us().rest_url());
+        assert_eq!(endpoints.ws_url(), "ws://127.0.0.1:9000");
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = Config::from_toml("[server]\nprot = 9000\n").unwrap_err();
+        assert!(err.to_string().contains("prot"));
+    }
+
+    #[test]
+    fn flags_override_file_values() {
+        let mut config = Config::from_toml("[server]\nport = 9000\n").unwrap();
+        config.apply_overrides(Cli {
+            port: Some(9100),
+            symbols: Some(vec!["BTCUSDT".to_string()]),
+            ..Cli::default()
+        });
+
+        assert_eq!(config.server.port, 9100);
+        assert_eq!(config.binance.symbols, vec!["BTCUSDT".to_string()]);
+    }
+
+    #[test]
+    fn validation_errors() {
+        let mut config = Config::default();
+        config.binance.symbols = vec!["DOGEUSDT".to_string()];
+        assert!(config.validate().unwrap_err().to_string().contains("unsupported symbol \"DOGEUSDT\""));
+
+        let mut config = Config::default();
+        config.binance.symbols = vec!["BTCUSDT".to_string(), "BTCUSDT".to_string()];
+        assert!(config.validate().unwrap_err().to_string().contains("more than once"));
+
+        let mut config = Config::default();
+        config.binance.snapshot_depth = 750;
+        assert!(config.validate().unwrap_err().to_string().contains("binance.snapshot_depth"));
+
+        let mut config = Config::default();
+        config.binance.ws_url = Some("https://stream.binance.com".to_string());
+        assert!(config.validate().unwrap_err().to_string().contains("ws or wss"));
+
+        let mut config = Config::default();
+        config.binance.profile = "devnet".to_string();
+        assert!(config.validate().unwrap_err().to_string().contains("binance.profile"));
+
+        let mut config = Config::default();
+        config.binance.profile = "custom".to_string();
+        config.binance.ws_url = Some("ws://127.0.0.1:9000".to_string());
+        assert!(config.validate().unwrap_err().to_string().contains("binance.rest_url is required"));
+
+        let mut config = Config::default();
+        config.replay.speed = "fast".to_string();
+        assert!(config.validate().unwrap_err().to_string().contains("replay.speed"));
+
+        let mut config = Config::default();
+        config.binance.stale_after_ms = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("binance.stale_after_ms"));
+
+        let mut config = Config::default();
+        config.reconnect.max_delay_ms = 10;
+        assert!(config.validate().unwrap_err().to_string().contains("reconnect.max_delay_ms"));
+
+        let mut config = Config::default();
+        config.server.shutdown_timeout_secs = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("server.shutdown_timeout_secs"));
+
+        let mut config = Config::default();
+        config.persistence.interval_secs = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("persistence.interval_secs"));
+
+        let mut config = Config::default();
+        config.history.capacity = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("history.capacity"));
+
+        let mut config = Config::default();
+        config.candles.intervals = vec!["1s".to_string(), "1w".to_string()];
+        assert!(config.validate().unwrap_err().to_string().contains("candles.intervals"));
+    }
+
+    #[test]
+    fn synthetic_pairs() {
+        let synthetic = r#"
+            [[synthetic]]
+            base = "ETH"
+            quote = "BTC"
+            legs = ["ETHUSDT", "BTCUSDT"]
+            "#;
+        let config = Config::from_toml(synthetic).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.synthetic_pairs()[0].symbol(), "ETHBTC");
+
+        let config = Config::from_toml(&format!("[binance]\nsymbols = [\"ETHUSDT\"]\n{}", synthetic)).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("not in binance.symbols"));
+
+        let config = Config::from_toml(&synthetic.replace("\"BTC\"", "\"USDC\"")).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("synthetic: Legs of ETHUSDC"));
+
+        let config = Config::from_toml(&format!("{}{}", synthetic, synthetic)).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("more than once"));
+    }
+}
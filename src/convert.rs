@@ -0,0 +1,213 @@
+use std::io::{Error, ErrorKind};
+
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::{
+    binance::assets_for_pair,
+    orderbook::{divide, Fill, OrderBook, Pair, Side},
+    synthetic::SyntheticPair,
+};
+
+/// Most books a conversion route goes through.
+pub const MAX_ROUTE_LEGS: usize = 3;
+
+/// Outcome of converting an amount of one asset into another along a route.
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    pub route: SyntheticPair,
+    /// The fill on each leg's book, in route order.
+    pub fills: Vec<Fill>,
+    pub amount: BigDecimal,
+    /// Amount of the target asset received.
+    pub output: BigDecimal,
+    /// `output` per unit of `amount`.
+    pub rate: BigDecimal,
+}
+
+/// A holding valued in another asset, at mid and at what selling it into the books gets.
+#[derive(Debug, Clone)]
+pub struct Valuation {
+    /// Amount times the mid of the route taken.
+    pub mark_value: BigDecimal,
+    pub liquidation_value: BigDecimal,
+    /// `mark_value - liquidation_value`.
+    pub haircut: BigDecimal,
+    /// Haircut relative to the mark value, in basis points.
+    pub haircut_bps: BigDecimal,
+    pub conversion: Conversion,
+}
+
+fn extend_routes(pairs: &[Pair], to: &str, max_legs: usize, assets: &mut Vec<&str>, legs: &mut Vec<Pair>, found: &mut Vec<Vec<Pair>>) {
+    let Some(asset) = assets.last().copied() else {
+        return;
+    };
+    if asset == to && !legs.is_empty() {
+        found.push(legs.clone());
+        return;
+    }
+    if legs.len() == max_legs {
+        return;
+    }
+
+    for pair in pairs {
+        let Some((base, quote)) = assets_for_pair(*pair) else {
+            continue;
+        };
+        let next = if base == asset {
+            quote
+        } else if quote == asset {
+            base
+        } else {
+            continue;
+        };
+        if legs.contains(pair) || assets.contains(&next) {
+            continue;
+        }
+
+        assets.push(next);
+        legs.push(*pair);
+        extend_routes(pairs, to, max_legs, assets, legs, found);
+        legs.pop();
+        assets.pop();
+    }
+}
+
+/// Every way from `from` to `to` through at most `max_legs` of `pairs`, never passing
+/// through the same asset twice.
+pub fn routes(pairs: &[Pair], from: &str, to: &str, max_legs: usize) -> Vec<SyntheticPair> {
+    let mut found = Vec::new();
+    extend_routes(pairs, to, max_legs, &mut vec![from], &mut Vec::new(), &mut found);
+    found.iter().filter_map(|legs| SyntheticPair::route(from, to, legs).ok()).collect()
+}
+
+/// The books `route` goes through, in leg order.
+fn books_for(route: &SyntheticPair, books: &[OrderBook]) -> Result<Vec<OrderBook>, Error> {
+    route
+        .pairs()
+        .iter()
+        .map(|pair| books.iter().find(|book| book.symbol() == *pair).cloned())
+        .collect::<Option<Vec<OrderBook>>>()
+        .ok_or_else(|| Error::other(format!("Missing a book for {:?}", route.pairs())))
+}
+
+/// Converts `amount` along each of `routes`, walking the depth of every book, and keeps
+/// the one that ends with the most. `books` must hold every pair the routes go through.
+pub fn best_conversion(routes: &[SyntheticPair], books: &[OrderBook], amount: &BigDecimal) -> Result<Conversion, Error> {
+    let mut best: Option<Conversion> = None;
+    let mut last_err = Error::new(ErrorKind::InvalidInput, "No route between these assets");
+
+    for route in routes {
+        let route_books = match books_for(route, books) {
+            Ok(route_books) => route_books,
+            Err(err) => {
+                last_err = err;
+                continue;
+            },
+        };
+
+        // Converting is selling the route's base for its quote
+        let fills = match route.leg_fills(Side::Sell, amount, &route_books) {
+            Ok(fills) => fills,
+            Err(err) => {
+                last_err = err;
+                continue;
+            },
+        };
+        let output = match (route.legs().last(), fills.last()) {
+            (Some(leg), Some(fill)) => leg.output(fill),
+            _ => continue,
+        };
+
+        if best.as_ref().is_none_or(|best| output > best.output) {
+            best = Some(Conversion {
+                route: route.clone(),
+                fills,
+                amount: amount.clone(),
+                rate: divide(&output, amount),
+                output,
+            });
+        }
+    }
+
+    best.ok_or(last_err)
+}
+
+/// Values `amount` of the routes' base in their quote, liquidating along the best route.
+pub fn value_holding(routes: &[SyntheticPair], books: &[OrderBook], amount: &BigDecimal) -> Result<Valuation, Error> {
+    let conversion = best_conversion(routes, books, amount)?;
+    let mark_value = amount * conversion.route.mid(&books_for(&conversion.route, books)?)?;
+
+    let haircut = &mark_value - &conversion.output;
+    Ok(Valuation {
+        liquidation_value: conversion.output.clone(),
+        haircut_bps: haircut_bps(&haircut, &mark_value),
+        mark_value,
+        haircut,
+        conversion,
+    })
+}
+
+/// `haircut` relative to `mark_value` in basis points, 0 for a worthless holding.
+pub fn haircut_bps(haircut: &BigDecimal, mark_value: &BigDecimal) -> BigDecimal {
+    if mark_value.is_zero() {
+        return BigDecimal::zero();
+    }
+    divide(&(haircut * BigDecimal::from(10_000)), mark_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use super::{best_conversion, routes, value_holding, MAX_ROUTE_LEGS};
+    use crate::orderbook::{OrderBook, Pair};
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn finds_and_walks_routes() {
+        let pairs = [Pair::BTCUSDT, Pair::ETHUSDT];
+        let books = vec![
+            OrderBook::new(Pair::BTCUSDT, vec![(decimal("100"), decimal("1"))], vec![(decimal("101"), decimal("1")), (decimal("102"), decimal("1"))], 1),
+            OrderBook::new(
+                Pair::ETHUSDT,
+                vec![(decimal("10"), decimal("5")), (decimal("9"), decimal("10"))],
+                vec![(decimal("11"), decimal("4")), (decimal("12"), decimal("10"))],
+                1,
+            ),
+        ];
+
+        let eth_to_btc = routes(&pairs, "ETH", "BTC", MAX_ROUTE_LEGS);
+        assert_eq!(eth_to_btc.len(), 1);
+        assert_eq!(eth_to_btc[0].pairs(), vec![Pair::ETHUSDT, Pair::BTCUSDT]);
+        assert!(routes(&pairs, "ETH", "BTC", 1).is_empty());
+        assert!(routes(&pairs, "ETH", "XRP", MAX_ROUTE_LEGS).is_empty());
+
+        // 50 USDT for 5 ETH buys 50 / 101 BTC
+        let conversion = best_conversion(&eth_to_btc, &books, &decimal("5")).unwrap();
+        assert_eq!(conversion.fills.len(), 2);
+        assert_eq!(conversion.fills[0].notional, decimal("50"));
+        assert_eq!(conversion.output, decimal("0.4950495049"));
+        assert_eq!(conversion.rate, decimal("0.099009901"));
+
+        // Spending USDT on ETH goes straight through one book
+        let usdt_to_eth = routes(&pairs, "USDT", "ETH", MAX_ROUTE_LEGS);
+        let conversion = best_conversion(&usdt_to_eth, &books, &decimal("56")).unwrap();
+        assert_eq!(conversion.output, decimal("5"));
+
+        // More ETH than the only route's first book takes
+        assert!(best_conversion(&eth_to_btc, &books, &decimal("16")).is_err());
+        assert!(best_conversion(&[], &books, &decimal("1")).is_err());
+
+        // 14 ETH at a mid of 10.5 against 5 at 10 and 9 at 9
+        let valuation = value_holding(&routes(&pairs, "ETH", "USDT", MAX_ROUTE_LEGS), &books, &decimal("14")).unwrap();
+        assert_eq!(valuation.mark_value, decimal("147"));
+        assert_eq!(valuation.liquidation_value, decimal("131"));
+        assert_eq!(valuation.haircut, decimal("16"));
+        assert_eq!(valuation.haircut_bps, decimal("1088.4353741497"));
+    }
+}
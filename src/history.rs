@@ -0,0 +1,338 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bigdecimal::{BigDecimal, Zero};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::orderbook::{divide, Pair, Tips};
+
+/// Best bid and ask of a book at a point in time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopOfBook {
+    /// Microseconds since the Unix epoch.
+    pub time: u64,
+    pub bid: BigDecimal,
+    pub bid_quantity: BigDecimal,
+    pub ask: BigDecimal,
+    pub ask_quantity: BigDecimal,
+    pub mid: BigDecimal,
+    pub spread: BigDecimal,
+}
+
+impl TopOfBook {
+    pub fn new(time: u64, tips: &Tips) -> TopOfBook {
+        let ((bid, bid_quantity), (ask, ask_quantity)) = tips.clone();
+        TopOfBook {
+            time,
+            mid: (&bid + &ask) / BigDecimal::from(2),
+            spread: &ask - &bid,
+            bid,
+            bid_quantity,
+            ask,
+            ask_quantity,
+        }
+    }
+
+    /// Mid weighted by the opposite side's quantity, leaning towards the side
+    /// more likely to move next.
+    pub fn microprice(&self) -> BigDecimal {
+        let total = &self.bid_quantity + &self.ask_quantity;
+        if total.is_zero() {
+            return self.mid.clone();
+        }
+        divide(&(&self.bid * &self.ask_quantity + &self.ask * &self.bid_quantity), &total)
+    }
+
+    fn same_tips(&self, other: &TopOfBook) -> bool {
+        self.bid == other.bid && self.bid_quantity == other.bid_quantity && self.ask == other.ask && self.ask_quantity == other.ask_quantity
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryConfig {
+    /// Entries kept in memory per pair.
+    pub capacity: usize,
+    /// When set, entries pushed out of memory are appended to a JSON lines file per pair.
+    pub spill_dir: Option<PathBuf>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig {
+            capacity: 100_000,
+            spill_dir: None,
+        }
+    }
+}
+
+/// The entries a history holds for a time range. Older entries may only be on disk,
+/// in which case `spill` points at the file to read them from with [`read_spill`].
+#[derive(Debug)]
+pub struct HistoryRange {
+    pub entries: Vec<TopOfBook>,
+    pub spill: Option<PathBuf>,
+}
+
+struct Spill {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl Spill {
+    fn append(&mut self, entry: &TopOfBook) -> Result<(), Error> {
+        if self.writer.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.writer = Some(BufWriter::new(file));
+        }
+
+        if let Some(writer) = self.writer.as_mut() {
+            serde_json::to_writer(&mut *writer, entry)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Bounded record of the top of book of one pair. Only changes are recorded, so an
+/// entry holds until the time of the next one.
+pub struct TopOfBookHistory {
+    capacity: usize,
+    entries: VecDeque<TopOfBook>,
+    spill: Option<Spill>,
+}
+
+impl TopOfBookHistory {
+    pub fn new(pair: Pair, config: &HistoryConfig) -> TopOfBookHistory {
+        TopOfBookHistory {
+            capacity: config.capacity.max(1),
+            entries: VecDeque::new(),
+            spill: config.spill_dir.as_ref().map(|dir| Spill {
+                path: dir.join(format!("{:?}-top-of-book.jsonl", pair)),
+                writer: None,
+            }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<&TopOfBook> {
+        self.entries.back()
+    }
+
+    /// Appends `entry` unless the tips did not change, evicting the oldest entry once full.
+    /// Returns whether the entry was recorded.
+    pub fn record(&mut self, entry: TopOfBook) -> bool {
+        if self.entries.back().is_some_and(|last| last.same_tips(&entry)) {
+            return false;
+        }
+
+        if self.entries.len() >= self.capacity {
+            if let Some(evicted) = self.entries.pop_front() {
+                if let Some(spill) = self.spill.as_mut() {
+                    if let Err(err) = spill.append(&evicted) {
+                        error!(path = %spill.path.display(), error = %err, "Failed to spill top of book history");
+                        self.spill = None;
+                    }
+                }
+            }
+        }
+        self.entries.push_back(entry);
+        true
+    }
+
+    /// Entries between `from` and `to` (inclusive, in microseconds), preceded by the
+    /// entry in effect at `from` when there is one.
+    pub fn range(&mut self, from: u64, to: u64) -> HistoryRange {
+        let covers_from = self.entries.front().is_some_and(|first| first.time <= from);
+        let spill = match self.spill.as_mut() {
+            Some(spill) if !covers_from => {
+                if let Err(err) = spill.flush() {
+                    error!(path = %spill.path.display(), error = %err, "Failed to flush top of book history");
+                }
+                Some(spill.path.clone()).filter(|path| path.exists())
+            },
+            _ => None,
+        };
+
+        HistoryRange {
+            entries: clip(self.entries.iter().cloned(), from, to),
+            spill,
+        }
+    }
+}
+
+/// Keeps the entries between `from` and `to` plus the last one before `from`.
+/// Expects entries in time order.
+pub fn clip(entries: impl IntoIterator<Item = TopOfBook>, from: u64, to: u64) -> Vec<TopOfBook> {
+    let mut clipped: Vec<TopOfBook> = Vec::new();
+    for entry in entries {
+        if entry.time > to {
+            break;
+        }
+        if entry.time < from {
+            clipped.clear();
+        }
+        clipped.push(entry);
+    }
+    clipped
+}
+
+/// Reads spilled entries in the same way [`TopOfBookHistory::range`] selects them.
+/// A torn last line, left by a write in progress, ends the read.
+pub fn read_spill(path: &Path, from: u64, to: u64) -> Result<Vec<TopOfBook>, Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        match serde_json::from_str::<TopOfBook>(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => break,
+        }
+    }
+    Ok(clip(entries, from, to))
+}
+
+/// One point per `interval` starting at `from`, holding the entry in effect at the
+/// end of the bucket. Buckets before the first entry are left out.
+pub fn resample(entries: &[TopOfBook], from: u64, to: u64, interval: Duration) -> Vec<TopOfBook> {
+    let step = (interval.as_micros() as u64).max(1);
+    let mut points = Vec::new();
+    let mut current: Option<&TopOfBook> = None;
+    let mut next = 0;
+
+    let mut bucket_start = from;
+    while bucket_start <= to {
+        let bucket_end = bucket_start.saturating_add(step - 1).min(to);
+        while next < entries.len() && entries[next].time <= bucket_end {
+            current = Some(&entries[next]);
+            next += 1;
+        }
+        if let Some(entry) = current {
+            points.push(TopOfBook {
+                time: bucket_start,
+                ..entry.clone()
+            });
+        }
+        bucket_start = match bucket_start.checked_add(step) {
+            Some(start) => start,
+            None => break,
+        };
+    }
+    points
+}
+
+/// Parses intervals such as `500ms`, `1s`, `5m` or `1h`.
+pub fn parse_interval(interval: &str) -> Result<Duration, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid interval {:?}, expected e.g. 500ms, 1s, 5m or 1h", interval));
+
+    let split = interval.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (value, unit) = interval.split_at(split);
+    let value = value.parse::<u64>().map_err(|_| invalid())?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 3600),
+        _ => return Err(invalid()),
+    };
+    if duration.is_zero() {
+        return Err(invalid());
+    }
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use bigdecimal::BigDecimal;
+
+    use super::{parse_interval, read_spill, resample, HistoryConfig, TopOfBook, TopOfBookHistory};
+    use crate::orderbook::Pair;
+
+    fn entry(time: u64, bid: u32, ask: u32) -> TopOfBook {
+        TopOfBook::new(time, &((BigDecimal::from(bid), BigDecimal::from(1)), (BigDecimal::from(ask), BigDecimal::from(1))))
+    }
+
+    #[test]
+    fn records_changes_and_spills_evictions() {
+        let dir = std::env::temp_dir().join(format!("challenge-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut history = TopOfBookHistory::new(Pair::BTCUSDT, &HistoryConfig {
+            capacity: 3,
+            spill_dir: Some(dir.clone()),
+        });
+        history.record(entry(10, 100, 102));
+        // Unchanged tips are not recorded again
+        history.record(entry(15, 100, 102));
+        history.record(entry(20, 101, 102));
+        history.record(entry(30, 101, 103));
+        history.record(entry(40, 99, 103));
+        history.record(entry(50, 98, 103));
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.latest().unwrap().mid, BigDecimal::new(1005.into(), 1));
+
+        let range = history.range(35, 45);
+        assert_eq!(range.entries.iter().map(|e| e.time).collect::<Vec<u64>>(), vec![30, 40]);
+        assert!(range.spill.is_none());
+
+        let range = history.range(12, 45);
+        assert_eq!(range.entries.iter().map(|e| e.time).collect::<Vec<u64>>(), vec![30, 40]);
+        let spilled = read_spill(&range.spill.unwrap(), 12, 45).unwrap();
+        assert_eq!(spilled.iter().map(|e| e.time).collect::<Vec<u64>>(), vec![10, 20]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resamples_carrying_the_last_entry() {
+        let entries = vec![entry(1_500_000, 100, 102), entry(1_700_000, 101, 102), entry(4_200_000, 99, 100)];
+        let points = resample(&entries, 1_000_000, 4_999_999, Duration::from_secs(1));
+
+        assert_eq!(points.iter().map(|p| p.time).collect::<Vec<u64>>(), vec![1_000_000, 2_000_000, 3_000_000, 4_000_000]);
+        assert_eq!(points[0].bid, BigDecimal::from(101));
+        assert_eq!(points[2].bid, BigDecimal::from(101));
+        assert_eq!(points[3].spread, BigDecimal::from(1));
+
+        assert!(resample(&entries, 0, 999_999, Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_interval("1s").unwrap(), Duration::from_secs(1));
+        assert_eq!(parse_interval("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_interval("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("s").is_err());
+        assert!(parse_interval("10").is_err());
+        assert!(parse_interval("1d").is_err());
+    }
+}
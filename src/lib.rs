@@ -1 +1,10 @@
+pub mod binance;
+pub mod candles;
+pub mod capture;
+pub mod convert;
+pub mod history;
+pub mod metrics;
+pub mod mock;
 pub mod orderbook;
+pub mod synthetic;
+pub mod time;
@@ -0,0 +1,31 @@
+use std::io::IsTerminal;
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogFormat, LogLevel, LoggingConfig};
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> LevelFilter {
+        match level {
+            LogLevel::Trace => LevelFilter::TRACE,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Error => LevelFilter::ERROR,
+        }
+    }
+}
+
+/// Installs the process wide subscriber. Directives in `RUST_LOG`, such as
+/// `challenge::orderbook=debug`, refine the configured level per module.
+pub fn init(config: &LoggingConfig) {
+    let filter = EnvFilter::builder().with_default_directive(LevelFilter::from(config.level).into()).from_env_lossy();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter).with_ansi(std::io::stdout().is_terminal());
+
+    match config.format {
+        LogFormat::Text => subscriber.init(),
+        // One object per line, with the fields of every enclosing span
+        LogFormat::Json => subscriber.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
@@ -1,29 +1,112 @@
-use actix_web::{App, HttpServer, web};
+use std::{
+    io::{Error, ErrorKind},
+    time::Duration,
+};
 
-mod prices;
-mod binance;
+use actix_web::{middleware, App, HttpServer, web};
+use challenge::{binance, orderbook::Pair, synthetic::SyntheticPair};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
 
-pub mod orderbook;
+mod config;
+mod logging;
+mod prices;
+mod status;
 
 struct AppState {
   binance_client: binance::BinanceClient,
+  /// The task feeding the books, it only ends when the client gives up or a replay is done.
+  stream_handle: JoinHandle<()>,
+  /// Pairs with a book, whatever the source.
+  pairs: Vec<Pair>,
+  synthetic_pairs: Vec<SyntheticPair>,
+}
+
+impl AppState {
+    fn synthetic_pair(&self, symbol: &str) -> Option<&SyntheticPair> {
+        self.synthetic_pairs.iter().find(|synthetic| synthetic.symbol() == symbol)
+    }
+}
+
+/// Resolves with the name of the first SIGINT or SIGTERM received.
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res.map(|()| "SIGINT"),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.map(|()| "ctrl-c")
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    logging::init(&config.logging);
+    tracing::info!(?config, "Starting");
 
-    let (binance_client, binance_handle) = binance::BinanceClient::new();
+    let (binance_client, stream_handle) = match config.replay_config() {
+        Some(replay_config) => binance::BinanceClient::replay(replay_config)?,
+        None => binance::BinanceClient::new(config.binance_config()),
+    };
 
     let app_data = web::Data::new(AppState {
         binance_client,
+        stream_handle,
+        pairs: config.binance_config().pairs,
+        synthetic_pairs: config.synthetic_pairs(),
     });
 
-    HttpServer::new(move || {
+    let server_data = app_data.clone();
+    let mut server = HttpServer::new(move || {
         App::new()
-            .app_data(app_data.clone())
+            .app_data(server_data.clone())
+            .wrap(middleware::from_fn(status::record_latency))
+            .configure(status::status_routes)
             .service(web::scope("/prices").configure(prices::price_routes))
     })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    // Signals are handled below, so the server stops before the books do
+    .disable_signals()
+    .shutdown_timeout(config.server.shutdown_timeout_secs);
+    if let Some(workers) = config.server.workers {
+        server = server.workers(workers);
+    }
+
+    let server = server.bind((config.server.host.as_str(), config.server.port))?.run();
+    let server_handle = server.handle();
+    let mut server_task = actix_web::rt::spawn(server);
+
+    tokio::select! {
+        res = &mut server_task => return res.map_err(Error::other)?,
+        signal = shutdown_signal() => info!(signal = signal?, "Shutting down"),
+    }
+
+    // Stops accepting connections and waits for in-flight requests, up to the timeout
+    server_handle.stop(true).await;
+    server_task.await.map_err(Error::other)??;
+
+    let timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    match tokio::time::timeout(timeout, app_data.binance_client.shutdown()).await {
+        Ok(Ok(())) => {
+            info!("Shut down cleanly");
+            Ok(())
+        },
+        Ok(Err(err)) => {
+            error!(error = %err, "Failed to shut down the books");
+            Err(err)
+        },
+        Err(_) => {
+            error!(timeout_secs = timeout.as_secs(), "Timed out shutting down the books");
+            Err(Error::new(ErrorKind::TimedOut, "Timed out shutting down the books"))
+        },
+    }
 }
@@ -0,0 +1,136 @@
+use std::{io::Error, sync::LazyLock};
+
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+
+use crate::orderbook::Pair;
+
+/// Label for frames whose symbol could not be read or is not a known pair.
+pub const UNKNOWN_SYMBOL: &str = "unknown";
+
+/// Snapshot requests go over the internet, HTTP requests are mostly served from memory.
+const SNAPSHOT_BUCKETS: [f64; 9] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+const HTTP_BUCKETS: [f64; 10] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Process wide metrics, served in the Prometheus text format on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    /// Websocket text frames, by the symbol they carry.
+    pub ws_messages_received: IntCounterVec,
+    pub ws_messages_parsed: IntCounterVec,
+    pub ws_messages_failed: IntCounterVec,
+    pub diffs_applied: IntCounterVec,
+    /// By symbol and reason: `stale`, `gap`, `untracked` or `resync`.
+    pub diffs_dropped: IntCounterVec,
+    /// Snapshots requested because the stream could not be applied to a book.
+    pub resyncs: IntCounterVec,
+    /// Checks of live books against REST snapshots, by symbol and outcome:
+    /// `match`, `mismatch` or `inconclusive`.
+    pub verifications: IntCounterVec,
+    /// Levels found to differ from a snapshot.
+    pub verification_mismatches: IntCounterVec,
+    /// By symbol and invariant: `crossed`, `empty_level` or `out_of_order`.
+    pub invariant_violations: IntCounterVec,
+    /// Messages waiting in the orderbook manager channel.
+    pub manager_queue_depth: IntGauge,
+    /// By symbol and side, 0 while the book is bootstrapping.
+    pub book_levels: IntGaugeVec,
+    pub snapshot_fetch_seconds: HistogramVec,
+    /// By method, route pattern and status.
+    pub http_request_seconds: HistogramVec,
+}
+
+/// The metrics every part of the process records into.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Label value for `pair`, e.g. `BTCUSDT`.
+pub fn symbol_label(pair: Pair) -> String {
+    format!("{:?}", pair)
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let histogram = |name: &str, help: &str, labels: &[&str], buckets: &[f64]| {
+            let histogram = HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets.to_vec()), labels).unwrap();
+            registry.register(Box::new(histogram.clone())).unwrap();
+            histogram
+        };
+
+        let ws_messages_received = counter("ws_messages_received_total", "Websocket text frames received", &["symbol"]);
+        let ws_messages_parsed = counter("ws_messages_parsed_total", "Websocket frames parsed into a diff", &["symbol"]);
+        let ws_messages_failed = counter("ws_messages_failed_total", "Websocket frames that could not be parsed", &["symbol"]);
+        let diffs_applied = counter("diffs_applied_total", "Diffs applied to a live book", &["symbol"]);
+        let diffs_dropped = counter("diffs_dropped_total", "Diffs discarded without being applied", &["symbol", "reason"]);
+        let resyncs = counter("resyncs_total", "Books dropped to be rebuilt from a fresh snapshot", &["symbol"]);
+        let verifications = counter("verifications_total", "Checks of a live book against a REST snapshot", &["symbol", "outcome"]);
+        let verification_mismatches = counter("verification_mismatched_levels_total", "Levels that differed from a REST snapshot", &["symbol"]);
+        let invariant_violations = counter("invariant_violations_total", "Broken invariants found in a live book", &["symbol", "invariant"]);
+        let snapshot_fetch_seconds = histogram("snapshot_fetch_seconds", "Time taken to fetch a REST depth snapshot", &["symbol"], &SNAPSHOT_BUCKETS);
+        let http_request_seconds = histogram("http_request_seconds", "Time taken to serve an HTTP request", &["method", "route", "status"], &HTTP_BUCKETS);
+
+        let manager_queue_depth = IntGauge::new("manager_queue_depth", "Messages waiting for the orderbook manager").unwrap();
+        registry.register(Box::new(manager_queue_depth.clone())).unwrap();
+        let book_levels = IntGaugeVec::new(Opts::new("book_levels", "Price levels held in a book"), &["symbol", "side"]).unwrap();
+        registry.register(Box::new(book_levels.clone())).unwrap();
+
+        Metrics {
+            registry,
+            ws_messages_received,
+            ws_messages_parsed,
+            ws_messages_failed,
+            diffs_applied,
+            diffs_dropped,
+            resyncs,
+            verifications,
+            verification_mismatches,
+            invariant_violations,
+            manager_queue_depth,
+            book_levels,
+            snapshot_fetch_seconds,
+            http_request_seconds,
+        }
+    }
+
+    /// Every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<String, Error> {
+        TextEncoder::new().encode_to_string(&self.registry.gather()).map_err(Error::other)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{symbol_label, Metrics};
+    use crate::orderbook::Pair;
+
+    #[test]
+    fn renders_labeled_metrics() {
+        let metrics = Metrics::new();
+        let symbol = symbol_label(Pair::BTCUSDT);
+        metrics.diffs_applied.with_label_values(&[&symbol]).inc_by(3);
+        metrics.diffs_dropped.with_label_values(&[&symbol, "stale"]).inc();
+        metrics.book_levels.with_label_values(&[&symbol, "bids"]).set(20);
+        metrics.snapshot_fetch_seconds.with_label_values(&[&symbol]).observe(0.2);
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains("diffs_applied_total{symbol=\"BTCUSDT\"} 3"));
+        assert!(text.contains("diffs_dropped_total{reason=\"stale\",symbol=\"BTCUSDT\"} 1"));
+        assert!(text.contains("book_levels{side=\"bids\",symbol=\"BTCUSDT\"} 20"));
+        assert!(text.contains("snapshot_fetch_seconds_bucket{symbol=\"BTCUSDT\",le=\"0.25\"} 1"));
+        assert!(text.contains("manager_queue_depth 0"));
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::Error,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{dev::ServerHandle, get, web, App, HttpRequest, HttpResponse, HttpServer};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    binance::BinanceEndpoints,
+    capture::{capture_files, CaptureReader, RecordKind},
+};
+
+/// What the mock serves. Snapshots are consumed in order per symbol, the last one
+/// keeps being served once the queue runs dry. Each websocket connection takes the
+/// next session script, connections past the end of the list stay open and silent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    /// Raw `/api/v3/depth` response bodies keyed by symbol.
+    #[serde(default)]
+    pub snapshots: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub sessions: Vec<Vec<Step>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Step {
+    /// Sends `text` verbatim, which allows malformed frames.
+    Frame { text: String },
+    /// Sends a combined stream `depthUpdate` event.
+    Diff {
+        symbol: String,
+        first_update_id: i64,
+        last_update_id: i64,
+        #[serde(default)]
+        bids: Vec<(String, String)>,
+        #[serde(default)]
+        asks: Vec<(String, String)>,
+    },
+    Sleep { millis: u64 },
+    /// Blocks the script until this many snapshots have been requested since startup.
+    WaitForSnapshots { count: usize },
+    /// Blocks the script until the test calls `MockBinance::release`.
+    WaitForRelease,
+    /// Drops the connection without a close frame.
+    Disconnect,
+    /// Sends a close frame and ends the session.
+    Close,
+}
+
+impl Scenario {
+    pub fn from_file(path: &Path) -> Result<Scenario, Error> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|err| Error::other(format!("Invalid scenario {}: {}", path.display(), err)))
+    }
+
+    /// Builds a scenario that plays back recorded traffic, from a single capture file
+    /// or every capture file in a directory. Each recorded connection becomes a
+    /// session sending the captured frames verbatim, and captured snapshots are served
+    /// in order. Frames recorded after a snapshot wait for that many snapshot requests,
+    /// so the client sees diffs in the same order relative to its snapshots.
+    pub fn from_capture(path: &Path) -> Result<Scenario, Error> {
+        let files = if path.is_dir() {
+            capture_files(path)?
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut scenario = Scenario::default();
+        let mut snapshots = 0;
+        for file in files {
+            for record in CaptureReader::open(&file)? {
+                // A file that was not finished cleanly ends in a truncated record
+                let Ok(record) = record else { break };
+                match record.kind {
+                    RecordKind::Connected => {
+                        // The recorded connection was lost, drop this one at the same point
+                        if let Some(session) = scenario.sessions.last_mut() {
+                            session.push(Step::Disconnect);
+                        }
+                        scenario.sessions.push(Vec::new());
+                    },
+                    RecordKind::Frame => {
+                        if scenario.sessions.is_empty() {
+                            scenario.sessions.push(Vec::new());
+                        }
+                        scenario.sessions.last_mut().unwrap().push(Step::Frame { text: record.data });
+                    },
+                    RecordKind::Snapshot => {
+                        let Some(symbol) = record.symbol else { continue };
+                        scenario.snapshots.entry(symbol).or_default().push(record.data);
+                        snapshots += 1;
+                        if let Some(session) = scenario.sessions.last_mut() {
+                            session.push(Step::WaitForSnapshots { count: snapshots });
+                        }
+                    },
+                }
+            }
+        }
+        Ok(scenario)
+    }
+
+    /// Queues a snapshot for `symbol` built from string levels.
+    pub fn snapshot(mut self, symbol: &str, last_update_id: i64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Scenario {
+        let body = json!({
+            "lastUpdateId": last_update_id,
+            "bids": bids.iter().map(|(p, q)| [p, q]).collect::<Vec<_>>(),
+            "asks": asks.iter().map(|(p, q)| [p, q]).collect::<Vec<_>>(),
+        });
+        self.snapshots.entry(symbol.to_string()).or_default().push(body.to_string());
+        self
+    }
+
+    pub fn session(mut self, steps: Vec<Step>) -> Scenario {
+        self.sessions.push(steps);
+        self
+    }
+}
+
+impl Step {
+    pub fn diff(symbol: &str, first_update_id: i64, last_update_id: i64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Step {
+        Step::Diff {
+            symbol: symbol.to_string(),
+            first_update_id,
+            last_update_id,
+            bids: bids.iter().map(|(p, q)| (p.to_string(), q.to_string())).collect(),
+            asks: asks.iter().map(|(p, q)| (p.to_string(), q.to_string())).collect(),
+        }
+    }
+
+    pub fn frame(text: &str) -> Step {
+        Step::Frame { text: text.to_string() }
+    }
+}
+
+fn diff_frame(symbol: &str, first_update_id: i64, last_update_id: i64, bids: &[(String, String)], asks: &[(String, String)]) -> String {
+    let event_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+    json!({
+        "stream": format!("{}@depth", symbol.to_lowercase()),
+        "data": {
+            "e": "depthUpdate",
+            "E": event_time as u64,
+            "s": symbol,
+            "U": first_update_id,
+            "u": last_update_id,
+            "b": bids.iter().map(|(p, q)| [p, q]).collect::<Vec<_>>(),
+            "a": asks.iter().map(|(p, q)| [p, q]).collect::<Vec<_>>(),
+        }
+    })
+    .to_string()
+}
+
+#[derive(Default)]
+struct MockState {
+    snapshots: HashMap<String, VecDeque<String>>,
+    sessions: VecDeque<Vec<Step>>,
+    snapshot_requests: usize,
+    connections: usize,
+    released: bool,
+    close_frames: usize,
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+#[derive(Deserialize)]
+struct DepthParams {
+    symbol: String,
+}
+
+#[get("/api/v3/depth")]
+async fn depth(params: web::Query<DepthParams>, state: web::Data<SharedState>) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    state.snapshot_requests += 1;
+
+    let body = match state.snapshots.get_mut(&params.symbol) {
+        Some(queue) if queue.len() > 1 => queue.pop_front(),
+        Some(queue) => queue.front().cloned(),
+        None => None,
+    };
+    match body {
+        Some(body) => HttpResponse::Ok().content_type("application/json").body(body),
+        None => HttpResponse::BadRequest().json(json!({"code": -1121, "msg": "Invalid symbol."})),
+    }
+}
+
+#[get("/stream")]
+async fn stream(req: HttpRequest, body: web::Payload, state: web::Data<SharedState>) -> actix_web::Result<HttpResponse> {
+    let (response, mut session, mut msg_stream) = actix_ws::handle(&req, body)?;
+
+    let steps = {
+        let mut state = state.lock().unwrap();
+        state.connections += 1;
+        state.sessions.pop_front().unwrap_or_default()
+    };
+    let state = state.into_inner();
+
+    actix_web::rt::spawn(async move {
+        for step in steps {
+            let sent = match step {
+                Step::Frame { text } => session.text(text).await,
+                Step::Diff { symbol, first_update_id, last_update_id, bids, asks } => {
+                    session.text(diff_frame(&symbol, first_update_id, last_update_id, &bids, &asks)).await
+                },
+                Step::Sleep { millis } => {
+                    tokio::time::sleep(Duration::from_millis(millis)).await;
+                    Ok(())
+                },
+                Step::WaitForSnapshots { count } => {
+                    while state.lock().unwrap().snapshot_requests < count {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                    Ok(())
+                },
+                Step::WaitForRelease => {
+                    while !std::mem::take(&mut state.lock().unwrap().released) {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                    Ok(())
+                },
+                Step::Disconnect => return,
+                Step::Close => {
+                    let _ = session.close(None).await;
+                    return;
+                },
+            };
+            if sent.is_err() {
+                return;
+            }
+        }
+
+        // Script done, keep the connection alive until the client leaves
+        while let Some(Ok(msg)) = msg_stream.recv().await {
+            let open = match msg {
+                actix_ws::Message::Ping(bytes) => session.pong(&bytes).await.is_ok(),
+                actix_ws::Message::Close(reason) => {
+                    state.lock().unwrap().close_frames += 1;
+                    let _ = session.close(reason).await;
+                    return;
+                },
+                _ => true,
+            };
+            if !open {
+                return;
+            }
+        }
+    });
+
+    Ok(response)
+}
+
+/// A running mock server. Requires an actix runtime, e.g. `#[actix_web::test]`.
+pub struct MockBinance {
+    addr: SocketAddr,
+    state: SharedState,
+    handle: ServerHandle,
+    server: actix_web::rt::task::JoinHandle<Result<(), Error>>,
+}
+
+impl MockBinance {
+    /// Starts the mock on a random local port.
+    pub async fn start(scenario: Scenario) -> Result<MockBinance, Error> {
+        MockBinance::bind(scenario, "127.0.0.1:0".parse().unwrap()).await
+    }
+
+    pub async fn bind(scenario: Scenario, addr: SocketAddr) -> Result<MockBinance, Error> {
+        let state: SharedState = Arc::new(Mutex::new(MockState {
+            snapshots: scenario.snapshots.into_iter().map(|(symbol, bodies)| (symbol, bodies.into())).collect(),
+            sessions: scenario.sessions.into(),
+            ..MockState::default()
+        }));
+
+        let app_state = web::Data::new(state.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .service(depth)
+                .service(stream)
+        })
+        .workers(1)
+        .bind(addr)?;
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+
+        Ok(MockBinance {
+            addr,
+            state,
+            handle,
+            server: actix_web::rt::spawn(server),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Plain `http://` and `ws://` endpoints pointing at this server.
+    pub fn endpoints(&self) -> BinanceEndpoints {
+        BinanceEndpoints::custom(&format!("http://{}", self.addr), &format!("ws://{}", self.addr)).unwrap()
+    }
+
+    pub fn snapshot_requests(&self) -> usize {
+        self.state.lock().unwrap().snapshot_requests
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// Close frames received from clients once their session's script was done.
+    pub fn close_frames(&self) -> usize {
+        self.state.lock().unwrap().close_frames
+    }
+
+    /// Lets a session blocked on `Step::WaitForRelease` carry on.
+    pub fn release(&self) {
+        self.state.lock().unwrap().released = true;
+    }
+
+    /// Resolves once the server stops.
+    pub async fn wait(self) -> Result<(), Error> {
+        self.server.await.map_err(Error::other)?
+    }
+
+    pub async fn stop(self) {
+        self.handle.stop(false).await;
+    }
+}
@@ -1,16 +1,91 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
 use bigdecimal::{BigDecimal, Zero};
-use tokio::{sync::{mpsc, oneshot}, task::JoinHandle};
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use tokio::{sync::{mpsc, oneshot}, task::JoinHandle, time::Interval};
+use tracing::{debug, debug_span, error, info, warn};
+
+use crate::{
+    candles::{Candle, CandleAggregator, CandleConfig},
+    history::{HistoryConfig, HistoryRange, TopOfBook, TopOfBookHistory},
+    metrics::{metrics, symbol_label},
+    time::now_micros,
+};
+
+mod analytics;
+mod depth;
+mod execution;
+mod invariants;
+mod persistence;
+mod status;
+mod verification;
+
+pub use analytics::{divide, imbalance, BandDepth, QUOTIENT_SCALE};
+pub use depth::{DepthChart, DepthPoint};
+pub use execution::{slippage_bps, Fill, FillLimit, Side};
+pub use invariants::{InvariantConfig, InvariantReaction, InvariantViolation};
+pub use persistence::{load_orderbook, save_orderbook, PersistenceConfig};
+pub use status::{BookState, BookStatus, DEFAULT_STALE_AFTER};
+pub use verification::{align, compare, BookSide, LevelMismatch, Verification, VerificationConfig, VERIFICATION_DIFF_BUFFER};
+
+use status::BookTracking;
 
 type Responder<T> = oneshot::Sender<T>;
 pub type OrderBookDepth = Vec<(BigDecimal, BigDecimal)>;
+/// Best bid and best ask, each as `(price, quantity)`.
+pub type Tips = ((BigDecimal, BigDecimal), (BigDecimal, BigDecimal));
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Pair {
     BTCUSDT,
     ETHUSDT,
 }
 
-#[derive(Debug)]
+impl<'de> Deserialize<'de> for Pair {
+    fn deserialize<D>(deserializer: D) -> Result<Pair, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "BTCUSDT" => Ok(Pair::BTCUSDT),
+            "ETHUSDT" => Ok(Pair::ETHUSDT),
+            _ => Err(serde::de::Error::custom("invalid pair")),
+        }
+    }
+}
+
+/// Pairs are written as their symbol, matching what `Deserialize` accepts.
+impl Serialize for Pair {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(&format_args!("{:?}", self))
+    }
+}
+
+/// Varint encoding keeps update ids and string lengths small.
+fn binary_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+fn to_binary<T: Serialize>(value: &T) -> Result<Vec<u8>, std::io::Error> {
+    binary_options().serialize(value).map_err(std::io::Error::other)
+}
+
+fn from_binary<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, std::io::Error> {
+    binary_options()
+        .deserialize(bytes)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+/// Decimals serialize as strings, so JSON keeps their exact precision.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBook {
     symbol: Pair,
     bids: OrderBookDepth,
@@ -28,37 +103,71 @@ impl OrderBook {
         }
     }
 
-    pub fn get_tips(&self) -> Result<((BigDecimal, BigDecimal), (BigDecimal, BigDecimal)), std::io::Error> {
+    pub fn symbol(&self) -> Pair {
+        self.symbol
+    }
+
+    /// Bids, best (highest) price first.
+    pub fn bids(&self) -> &OrderBookDepth {
+        &self.bids
+    }
+
+    /// Asks, best (lowest) price first.
+    pub fn asks(&self) -> &OrderBookDepth {
+        &self.asks
+    }
+
+    pub fn last_update_id(&self) -> i64 {
+        self.last_update_id
+    }
+
+    /// Compact binary encoding, see [`OrderBook::from_binary`].
+    pub fn to_binary(&self) -> Result<Vec<u8>, std::io::Error> {
+        to_binary(self)
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<OrderBook, std::io::Error> {
+        from_binary(bytes)
+    }
+
+    pub fn get_tips(&self) -> Result<Tips, std::io::Error> {
         let bid = self
             .bids
             .first()
             .map_or_else(
-                || Err(std::io::Error::new(std::io::ErrorKind::Other, "No bids")),
+                || Err(std::io::Error::other("No bids")),
                 |(price, amount)| Ok((price.clone(), amount.clone()))
             )?;
         let ask = self
             .asks
             .first()
             .map_or_else(
-                || Err(std::io::Error::new(std::io::ErrorKind::Other, "No asks")),
+                || Err(std::io::Error::other("No asks")),
                 |(price, amount)| Ok((price.clone(), amount.clone()))
             )?;
         Ok((bid, ask))
     }
 
-    pub fn handle_diff(&mut self, diff: OrderBookDiff) {
-        if diff.last_update_id <= self.last_update_id {
-            println!("Ignoring diff with last_update_id {} <= {}", diff.last_update_id, self.last_update_id);
-            return;
+    /// Fails when `diff` starts past the next update id, leaving a gap in the book.
+    pub fn check_diff(&self, diff: &OrderBookDiff) -> Result<(), std::io::Error> {
+        if diff.first_update_id > self.last_update_id + 1 {
+            return Err(std::io::Error::other(format!(
+                "Diff is too far ahead: {} -> {} vs {}",
+                diff.first_update_id, diff.last_update_id, self.last_update_id
+            )));
         }
+        Ok(())
+    }
 
-        if diff.first_update_id > self.last_update_id + 1 || diff.last_update_id <= self.last_update_id + 1 {
-            panic!("Diff is too far ahead or too far behind: {} -> {} vs {}", diff.first_update_id, diff.last_update_id, self.last_update_id);
+    /// Applies `diff`, ignoring it when the book is already past it. A diff past a gap
+    /// is rejected and leaves the book untouched.
+    pub fn handle_diff(&mut self, diff: OrderBookDiff) -> Result<(), std::io::Error> {
+        if diff.last_update_id <= self.last_update_id {
+            debug!(symbol = ?self.symbol, last_update_id = diff.last_update_id, book_update_id = self.last_update_id, "Ignoring stale diff");
+            return Ok(());
         }
 
-        if diff.first_update_id > self.last_update_id + 1 {
-            println!("Orderbook might be out of sync, TODO: fetching full orderbook");
-        }
+        self.check_diff(&diff)?;
 
         for (price, quantity) in diff.bids.into_iter() {
             let element_pos = self.bids.iter().position(|(p, _)| *p == price);
@@ -70,10 +179,10 @@ impl OrderBook {
                 if let Some(pos) = element_pos {
                     self.bids[pos] = (price, quantity);
                 } else {
-                    if price < self.bids.last().map(|(p, _)| p.clone()).unwrap_or_else(|| BigDecimal::zero()) {
+                    if price < self.bids.last().map(|(p, _)| p.clone()).unwrap_or_else(BigDecimal::zero) {
                         self.bids.push((price, quantity));
                         continue;
-                    } else if price > self.bids.first().map(|(p, _)| p.clone()).unwrap_or_else(|| BigDecimal::zero()) {
+                    } else if price > self.bids.first().map(|(p, _)| p.clone()).unwrap_or_else(BigDecimal::zero) {
                         self.bids.insert(0, (price, quantity));
                         continue;
                     } else {
@@ -98,10 +207,10 @@ impl OrderBook {
                 if let Some(pos) = element_pos {
                     self.asks[pos] = (price, quantity);
                 } else {
-                    if price > self.asks.last().map(|(p, _)| p.clone()).unwrap_or_else(|| BigDecimal::zero()) {
+                    if price > self.asks.last().map(|(p, _)| p.clone()).unwrap_or_else(BigDecimal::zero) {
                         self.asks.push((price, quantity));
                         continue;
-                    } else if price < self.asks.first().map(|(p, _)| p.clone()).unwrap_or_else(|| BigDecimal::zero()) {
+                    } else if price < self.asks.first().map(|(p, _)| p.clone()).unwrap_or_else(BigDecimal::zero) {
                         self.asks.insert(0, (price, quantity));
                         continue;
                     } else {
@@ -117,111 +226,632 @@ impl OrderBook {
         }
 
         self.last_update_id = diff.last_update_id;
-        // println!("Updated orderbook for {:?} {}", self.symbol, self.last_update_id);
-        // println!("Bids size {}", self.bids.len());
-        // println!("Asks size {}", self.asks.len());
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 pub enum OrderbookMessage {
     OrderbookDiff(Pair, OrderBookDiff),
-    Tips(Pair, Responder<Result<((BigDecimal, BigDecimal), (BigDecimal, BigDecimal)), std::io::Error>>),
-    Bids(Pair, Responder<OrderBookDepth>),
-    Asks(Pair, Responder<OrderBookDepth>),
+    Snapshot(OrderBook),
+    /// A book restored from disk. It only goes live once a diff bridges its
+    /// `last_update_id`, a diff past it discards the book and asks for a resync.
+    WarmStart(OrderBook),
+    Resync(Pair),
+    /// A REST snapshot to check the live book against, see [`OrderbookManager::with_verification`].
+    Verify(OrderBook),
+    Tips(Pair, Responder<Result<Tips, std::io::Error>>),
+    Bids(Pair, Responder<Result<OrderBookDepth, std::io::Error>>),
+    Asks(Pair, Responder<Result<OrderBookDepth, std::io::Error>>),
+    /// A copy of the whole book, for computations that need both sides at one update id.
+    Book(Pair, Responder<Result<OrderBook, std::io::Error>>),
+    /// Copies of several books from the same point in the stream.
+    Books(Vec<Pair>, Responder<Vec<Result<OrderBook, std::io::Error>>>),
+    /// Top of book history between two times in microseconds, see [`TopOfBookHistory::range`].
+    History(Pair, u64, u64, Responder<Result<HistoryRange, std::io::Error>>),
+    /// The latest bars of an interval, see [`CandleAggregator::candles`].
+    Candles(Pair, Duration, usize, Responder<Result<Vec<Candle>, std::io::Error>>),
+    /// The status of every tracked book, ordered by symbol.
+    Status(Responder<Vec<BookStatus>>),
+    /// Capture time of the record being replayed, in microseconds. History, candles and
+    /// book status use the latest one instead of the wall clock from then on.
+    Clock(u64),
+    /// Stops a spawned manager once the messages already queued are handled, see
+    /// [`OrderbookManager::spawn`]. Answered when it is done.
+    Shutdown(Responder<()>),
 }
 
+/// Holds one orderbook per tracked pair. A pair without a book is bootstrapping:
+/// its diffs are buffered until a snapshot arrives and are then replayed on top of it.
+/// When a gap in update ids is found the book goes back to bootstrapping and a fresh
+/// snapshot is requested through `resync_tx`. With persistence enabled, live books
+/// are periodically written to disk so a restart can warm start from them.
 pub struct OrderbookManager {
-    orderbooks: [Option<OrderBook>; 2],
+    orderbooks: HashMap<Pair, Option<OrderBook>>,
+    pending_diffs: HashMap<Pair, Vec<OrderBookDiff>>,
+    warm_books: HashMap<Pair, OrderBook>,
+    histories: HashMap<Pair, TopOfBookHistory>,
+    candles: HashMap<Pair, CandleAggregator>,
+    resync_tx: Option<mpsc::UnboundedSender<Pair>>,
+    persistence: Option<PersistenceConfig>,
+    /// Update id of the last book saved successfully for each pair.
+    persisted_ids: HashMap<Pair, i64>,
+    /// Saves still running on the blocking pool, with the update id being saved.
+    saves: HashMap<Pair, (i64, JoinHandle<bool>)>,
+    tracking: HashMap<Pair, BookTracking>,
+    /// `None` never reports a book as stale.
+    stale_after: Option<Duration>,
+    verification: Option<VerificationConfig>,
+    /// Diffs applied to each live book since it went live, the latest
+    /// [`VERIFICATION_DIFF_BUFFER`] of them. Only kept with verification on.
+    recent_diffs: HashMap<Pair, VecDeque<OrderBookDiff>>,
+    /// Snapshots ahead of their live book, checked once the book catches up.
+    pending_checks: HashMap<Pair, OrderBook>,
+    invariants: InvariantConfig,
+    /// Diffs applied to each book, to sample invariant checks.
+    applied_diffs: HashMap<Pair, u64>,
+    /// Set by [`OrderbookMessage::Clock`] when replaying, see [`OrderbookManager::now`].
+    clock: Option<u64>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct OrderBookDiff {
     pub bids: OrderBookDepth,
     pub asks: OrderBookDepth,
     pub first_update_id: i64,
     pub last_update_id: i64,
+    /// When the exchange emitted the diff, in microseconds since the Unix epoch.
+    pub event_time: Option<u64>,
 }
 
-pub fn start_orderbook_manager(orderbook_btc: OrderBook, orderbook_eth: OrderBook, mut rx: mpsc::UnboundedReceiver<OrderbookMessage>) -> JoinHandle<()> {
-    return tokio::spawn(async move {
-        let mut state =     OrderbookManager {
-            orderbooks: [Some(orderbook_btc), Some(orderbook_eth)],
-        };
+impl OrderBookDiff {
+    /// Compact binary encoding, see [`OrderBookDiff::from_binary`].
+    pub fn to_binary(&self) -> Result<Vec<u8>, std::io::Error> {
+        to_binary(self)
+    }
 
-        while let Some(msg) = rx.recv().await {
-            match msg {
-                OrderbookMessage::OrderbookDiff(pair, diff) => {
-                    match pair {
-                        Pair::BTCUSDT => {
-                            if let Some(orderbook) = &mut state.orderbooks[0] {
-                                orderbook.handle_diff(diff);
-                            }
-                        },
-                        Pair::ETHUSDT => {
-                            if let Some(orderbook) = &mut state.orderbooks[1] {
-                                orderbook.handle_diff(diff);
-                            }
-                        },
-                    }
-                },
-                OrderbookMessage::Tips(pair, resp) => {
-                    match pair {
-                        Pair::BTCUSDT => {
-                            if let Some(orderbook) = &state.orderbooks[0] {
-                                let _ = resp.send(orderbook.get_tips());
-                            }
-                        },
-                        Pair::ETHUSDT => {
-                            if let Some(orderbook) = &state.orderbooks[1] {
-                                let _ = resp.send(orderbook.get_tips());
-                            }
-                        },
-                    }
-                },
-                OrderbookMessage::Bids(pair, resp) => {
-                    match pair {
-                        Pair::BTCUSDT => {
-                            if let Some(orderbook) = &state.orderbooks[0] {
-                                let bids = orderbook.bids.clone();
-                                let _ = resp.send(bids);
-                            }
+    pub fn from_binary(bytes: &[u8]) -> Result<OrderBookDiff, std::io::Error> {
+        from_binary(bytes)
+    }
+}
+
+impl OrderbookManager {
+    pub fn new(pairs: &[Pair]) -> OrderbookManager {
+        OrderbookManager {
+            orderbooks: pairs.iter().map(|pair| (*pair, None)).collect(),
+            pending_diffs: pairs.iter().map(|pair| (*pair, Vec::new())).collect(),
+            warm_books: HashMap::new(),
+            histories: pairs.iter().map(|pair| (*pair, TopOfBookHistory::new(*pair, &HistoryConfig::default()))).collect(),
+            candles: pairs.iter().map(|pair| (*pair, CandleAggregator::new(&CandleConfig::default()))).collect(),
+            resync_tx: None,
+            persistence: None,
+            persisted_ids: HashMap::new(),
+            saves: HashMap::new(),
+            tracking: pairs.iter().map(|pair| (*pair, BookTracking::default())).collect(),
+            stale_after: Some(DEFAULT_STALE_AFTER),
+            verification: None,
+            recent_diffs: HashMap::new(),
+            pending_checks: HashMap::new(),
+            invariants: InvariantConfig::default(),
+            applied_diffs: HashMap::new(),
+            clock: None,
+        }
+    }
+
+    pub fn with_resync_requests(mut self, resync_tx: mpsc::UnboundedSender<Pair>) -> OrderbookManager {
+        self.resync_tx = Some(resync_tx);
+        self
+    }
+
+    pub fn with_history(mut self, history: &HistoryConfig) -> OrderbookManager {
+        for (pair, pair_history) in self.histories.iter_mut() {
+            *pair_history = TopOfBookHistory::new(*pair, history);
+        }
+        self
+    }
+
+    pub fn with_candles(mut self, candles: &CandleConfig) -> OrderbookManager {
+        for pair_candles in self.candles.values_mut() {
+            *pair_candles = CandleAggregator::new(candles);
+        }
+        self
+    }
+
+    /// Live books that go this long without a diff are reported as [`BookState::Stale`].
+    pub fn with_stale_after(mut self, stale_after: Duration) -> OrderbookManager {
+        self.stale_after = Some(stale_after);
+        self
+    }
+
+    /// Never reports books as stale, e.g. for a replay that has ended.
+    pub fn without_staleness(mut self) -> OrderbookManager {
+        self.stale_after = None;
+        self
+    }
+
+    /// Keeps the diffs needed to check live books against the snapshots sent with
+    /// [`OrderbookMessage::Verify`].
+    pub fn with_verification(mut self, verification: VerificationConfig) -> OrderbookManager {
+        self.verification = Some(verification);
+        self
+    }
+
+    pub fn with_invariants(mut self, invariants: InvariantConfig) -> OrderbookManager {
+        self.invariants = invariants;
+        self
+    }
+
+    pub fn with_persistence(mut self, persistence: PersistenceConfig) -> OrderbookManager {
+        self.persistence = Some(persistence);
+        self
+    }
+
+    /// Runs the manager until every sender of `rx` is dropped or it is told to shut down.
+    /// On shutdown the queued messages are drained and, with `save_on_shutdown`, the
+    /// live books are saved a last time.
+    pub fn spawn(mut self, mut rx: mpsc::UnboundedReceiver<OrderbookMessage>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut persist_interval = self.persistence.as_ref().map(|persistence| tokio::time::interval(persistence.interval));
+
+            loop {
+                tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Some(OrderbookMessage::Shutdown(responder)) => {
+                            self.shut_down(&mut rx).await;
+                            let _ = responder.send(());
+                            break;
                         },
-                        Pair::ETHUSDT => {
-                            if let Some(orderbook) = &state.orderbooks[1] {
-                                let bids = orderbook.bids.clone();
-                                let _ = resp.send(bids);
-                            }
+                        Some(msg) => {
+                            metrics().manager_queue_depth.set(rx.len() as i64);
+                            self.handle_message(msg);
                         },
-                    }
-                },
-                OrderbookMessage::Asks(pair, resp) => {
-                    match pair {
-                        Pair::BTCUSDT => {
-                            if let Some(orderbook) = &state.orderbooks[0] {
-                                let asks = orderbook.asks.clone();
-                                let _ = resp.send(asks);
+                        None => break,
+                    },
+                    _ = tick(&mut persist_interval) => self.persist_books().await,
+                }
+            }
+        })
+    }
+
+    pub fn handle_message(&mut self, msg: OrderbookMessage) {
+        match msg {
+            OrderbookMessage::OrderbookDiff(pair, diff) => {
+                let now = self.now();
+                let _span = debug_span!("apply_diff", symbol = ?pair, first_update_id = diff.first_update_id, last_update_id = diff.last_update_id).entered();
+                match self.orderbooks.get_mut(&pair) {
+                    Some(Some(orderbook)) => {
+                        if let Err(err) = orderbook.check_diff(&diff) {
+                            warn!(error = %err, "Gap in diffs, resyncing");
+                            self.request_resync(pair, vec![diff]);
+                        } else {
+                            let event_time = diff.event_time;
+                            let kept = self.verification.is_some().then(|| diff.clone());
+                            if apply_diff(orderbook, diff) {
+                                self.tracking.entry(pair).or_default().diff_applied(now, event_time);
+                                if let Some(diff) = kept {
+                                    self.keep_diff(pair, diff);
+                                }
+                                let applied = self.applied_diffs.entry(pair).or_default();
+                                *applied += 1;
+                                if self.invariants.is_due(*applied) {
+                                    self.check_invariants(pair);
+                                }
                             }
-                        },
-                        Pair::ETHUSDT => {
-                            if let Some(orderbook) = &state.orderbooks[1] {
-                                let asks = orderbook.asks.clone();
-                                let _ = resp.send(asks);
+                        }
+                    },
+                    Some(None) => match self.warm_books.remove(&pair) {
+                        Some(mut warm_book) => {
+                            if diff.last_update_id <= warm_book.last_update_id {
+                                metrics().diffs_dropped.with_label_values(&[&symbol_label(pair), "stale"]).inc();
+                                self.warm_books.insert(pair, warm_book);
+                            } else if warm_book.check_diff(&diff).is_ok() {
+                                info!(book_update_id = warm_book.last_update_id, "Saved book lines up with the stream, warm start done");
+                                let event_time = diff.event_time;
+                                apply_diff(&mut warm_book, diff);
+                                self.orderbooks.insert(pair, Some(warm_book));
+                                let tracking = self.tracking.entry(pair).or_default();
+                                tracking.live_at = Some(now);
+                                tracking.diff_applied(now, event_time);
+                                self.check_invariants(pair);
+                            } else {
+                                warn!(book_update_id = warm_book.last_update_id, "Saved book is behind the stream, resyncing");
+                                self.request_resync(pair, vec![diff]);
                             }
                         },
+                        None => self.pending_diffs.entry(pair).or_default().push(diff),
+                    },
+                    None => {
+                        warn!("Dropping diff for untracked pair");
+                        metrics().diffs_dropped.with_label_values(&[&symbol_label(pair), "untracked"]).inc();
+                    },
+                }
+                self.record_tips(pair);
+                self.record_levels(pair);
+            },
+            OrderbookMessage::Snapshot(mut orderbook) => {
+                let pair = orderbook.symbol;
+                if !self.orderbooks.contains_key(&pair) {
+                    warn!(symbol = ?pair, "Dropping snapshot for untracked pair");
+                    return;
+                }
+                self.warm_books.remove(&pair);
+                self.forget_diffs(pair);
+                let mut pending = std::mem::take(self.pending_diffs.entry(pair).or_default()).into_iter();
+                while let Some(diff) = pending.next() {
+                    if let Err(err) = orderbook.check_diff(&diff) {
+                        warn!(symbol = ?pair, error = %err, "Snapshot is older than buffered diffs, resyncing");
+                        self.request_resync(pair, std::iter::once(diff).chain(pending).collect());
+                        return;
                     }
-                },
+                    apply_diff(&mut orderbook, diff);
+                }
+                info!(symbol = ?pair, last_update_id = orderbook.last_update_id, "Book is live");
+                self.orderbooks.insert(pair, Some(orderbook));
+                let now = self.now();
+                self.tracking.entry(pair).or_default().live_at = Some(now);
+                self.check_invariants(pair);
+                self.record_tips(pair);
+                self.record_levels(pair);
+            },
+            OrderbookMessage::WarmStart(orderbook) => {
+                let pair = orderbook.symbol;
+                match self.orderbooks.get(&pair) {
+                    Some(None) => {},
+                    Some(Some(_)) => return,
+                    None => {
+                        warn!(symbol = ?pair, "Dropping saved book for untracked pair");
+                        return;
+                    },
+                }
+                self.warm_books.insert(pair, orderbook);
+                for diff in std::mem::take(self.pending_diffs.entry(pair).or_default()) {
+                    self.handle_message(OrderbookMessage::OrderbookDiff(pair, diff));
+                }
+            },
+            OrderbookMessage::Resync(pair) => {
+                self.forget_diffs(pair);
+                if let Some(slot) = self.orderbooks.get_mut(&pair) {
+                    *slot = None;
+                    let dropped = std::mem::take(self.pending_diffs.entry(pair).or_default()).len();
+                    metrics().diffs_dropped.with_label_values(&[&symbol_label(pair), "resync"]).inc_by(dropped as u64);
+                    self.warm_books.remove(&pair);
+                    self.record_levels(pair);
+                }
+            },
+            OrderbookMessage::Verify(snapshot) => {
+                let pair = snapshot.symbol;
+                if self.verification.is_none() || !matches!(self.orderbooks.get(&pair), Some(Some(_))) {
+                    return;
+                }
+                self.pending_checks.insert(pair, snapshot);
+                self.run_check(pair);
+            },
+            OrderbookMessage::Tips(pair, resp) => {
+                let _ = resp.send(self.orderbook(pair).and_then(|orderbook| orderbook.get_tips()));
+            },
+            OrderbookMessage::Bids(pair, resp) => {
+                let _ = resp.send(self.orderbook(pair).map(|orderbook| orderbook.bids.clone()));
+            },
+            OrderbookMessage::Asks(pair, resp) => {
+                let _ = resp.send(self.orderbook(pair).map(|orderbook| orderbook.asks.clone()));
+            },
+            OrderbookMessage::Book(pair, resp) => {
+                let _ = resp.send(self.orderbook(pair).cloned());
+            },
+            OrderbookMessage::Books(pairs, resp) => {
+                let books = pairs.into_iter().map(|pair| self.orderbook(pair).cloned()).collect();
+                let _ = resp.send(books);
+            },
+            OrderbookMessage::History(pair, from, to, resp) => {
+                let range = match self.histories.get_mut(&pair) {
+                    Some(history) => Ok(history.range(from, to)),
+                    None => Err(std::io::Error::other(format!("Pair {:?} is not tracked", pair))),
+                };
+                let _ = resp.send(range);
+            },
+            OrderbookMessage::Candles(pair, interval, limit, resp) => {
+                let candles = match self.candles.get(&pair) {
+                    Some(candles) => candles.candles(interval, limit).ok_or_else(|| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("No {:?} candles are built", interval))
+                    }),
+                    None => Err(std::io::Error::other(format!("Pair {:?} is not tracked", pair))),
+                };
+                let _ = resp.send(candles);
+            },
+            OrderbookMessage::Status(resp) => {
+                let _ = resp.send(self.status(self.now()));
+            },
+            OrderbookMessage::Clock(at) => {
+                self.clock = Some(at);
+            },
+            // Only a spawned manager stops, a repeated request is answered right away
+            OrderbookMessage::Shutdown(resp) => {
+                let _ = resp.send(());
+            },
+        }
+    }
+
+    /// Status of every tracked book at `now` (microseconds), ordered by symbol.
+    pub fn status(&self, now: u64) -> Vec<BookStatus> {
+        let mut statuses: Vec<BookStatus> = self
+            .orderbooks
+            .iter()
+            .map(|(pair, orderbook)| {
+                let tracking = self.tracking.get(pair).cloned().unwrap_or_default();
+                tracking.status(*pair, orderbook.as_ref().map(|orderbook| orderbook.last_update_id), self.stale_after, now)
+            })
+            .collect();
+        statuses.sort_by_key(|status| symbol_label(status.symbol));
+        statuses
+    }
+
+    /// Current time in microseconds, the replay clock if one was set.
+    fn now(&self) -> u64 {
+        self.clock.unwrap_or_else(now_micros)
+    }
+
+    /// Adds the current tips of `pair` to its history and candles if the book is live
+    /// and they changed.
+    fn record_tips(&mut self, pair: Pair) {
+        let now = self.now();
+        let Some(Some(orderbook)) = self.orderbooks.get(&pair) else {
+            return;
+        };
+        let (Ok(tips), Some(history)) = (orderbook.get_tips(), self.histories.get_mut(&pair)) else {
+            return;
+        };
+        let entry = TopOfBook::new(now, &tips);
+        if history.record(entry.clone()) {
+            if let Some(candles) = self.candles.get_mut(&pair) {
+                candles.update(&entry);
+            }
+        }
+    }
+
+    /// Sets the level count gauges of `pair`, zero while it has no book.
+    fn record_levels(&self, pair: Pair) {
+        let (bids, asks) = match self.orderbooks.get(&pair) {
+            Some(Some(orderbook)) => (orderbook.bids.len(), orderbook.asks.len()),
+            Some(None) => (0, 0),
+            None => return,
+        };
+        let symbol = symbol_label(pair);
+        metrics().book_levels.with_label_values(&[&symbol, "bids"]).set(bids as i64);
+        metrics().book_levels.with_label_values(&[&symbol, "asks"]).set(asks as i64);
+    }
+
+    /// Looks for broken invariants in the live book of `pair` and reacts as configured.
+    fn check_invariants(&mut self, pair: Pair) {
+        let Some(Some(orderbook)) = self.orderbooks.get(&pair) else {
+            return;
+        };
+        let violations = orderbook.invariant_violations();
+        if violations.is_empty() {
+            return;
+        }
+
+        let symbol = symbol_label(pair);
+        for violation in violations.iter() {
+            metrics().invariant_violations.with_label_values(&[&symbol, violation.name()]).inc();
+        }
+        if self.invariants.reaction == InvariantReaction::Metric {
+            return;
+        }
+        error!(symbol = ?pair, last_update_id = orderbook.last_update_id, ?violations, "Book breaks its invariants");
+        if self.invariants.reaction == InvariantReaction::Resync {
+            self.request_resync(pair, Vec::new());
+        }
+    }
+
+    /// Remembers a diff applied to the live book of `pair` and runs a pending check
+    /// that it may have made possible.
+    fn keep_diff(&mut self, pair: Pair, diff: OrderBookDiff) {
+        let diffs = self.recent_diffs.entry(pair).or_default();
+        if diffs.len() == VERIFICATION_DIFF_BUFFER {
+            diffs.pop_front();
+        }
+        diffs.push_back(diff);
+        self.run_check(pair);
+    }
+
+    /// The book of `pair` is replaced, earlier diffs and checks no longer apply to it.
+    fn forget_diffs(&mut self, pair: Pair) {
+        self.recent_diffs.remove(&pair);
+        self.pending_checks.remove(&pair);
+    }
+
+    /// Checks the live book of `pair` against its pending snapshot once the book has
+    /// reached the snapshot's update id.
+    fn run_check(&mut self, pair: Pair) {
+        let (Some(Some(orderbook)), Some(snapshot)) = (self.orderbooks.get(&pair), self.pending_checks.get(&pair)) else {
+            return;
+        };
+        if orderbook.last_update_id < snapshot.last_update_id {
+            return;
+        }
+        let Some(snapshot) = self.pending_checks.remove(&pair) else {
+            return;
+        };
+
+        let empty = VecDeque::new();
+        let diffs = self.recent_diffs.get(&pair).unwrap_or(&empty);
+        let outcome = match align(snapshot.clone(), diffs, orderbook.last_update_id) {
+            Some(reference) => match compare(orderbook, &reference, &snapshot) {
+                mismatches if mismatches.is_empty() => Verification::Match,
+                mismatches => Verification::Mismatch(mismatches),
+            },
+            None => Verification::Inconclusive,
+        };
+
+        let symbol = symbol_label(pair);
+        let label = match &outcome {
+            Verification::Match => "match",
+            Verification::Mismatch(mismatches) => {
+                warn!(
+                    symbol = ?pair,
+                    last_update_id = orderbook.last_update_id,
+                    snapshot_update_id = snapshot.last_update_id,
+                    levels = mismatches.len(),
+                    first = ?mismatches[0],
+                    "Book differs from a snapshot"
+                );
+                metrics().verification_mismatches.with_label_values(&[&symbol]).inc_by(mismatches.len() as u64);
+                "mismatch"
+            },
+            Verification::Inconclusive => {
+                debug!(symbol = ?pair, last_update_id = orderbook.last_update_id, snapshot_update_id = snapshot.last_update_id, "Could not line up snapshot with the book");
+                "inconclusive"
+            },
+        };
+        metrics().verifications.with_label_values(&[&symbol, label]).inc();
+
+        let resync = self.verification.as_ref().is_some_and(|verification| verification.resync_on_mismatch);
+        if resync && matches!(outcome, Verification::Mismatch(_)) {
+            self.request_resync(pair, Vec::new());
+        }
+    }
+
+    /// Drops the book for `pair`, keeping `pending` as the buffered diffs, and asks for a new snapshot.
+    fn request_resync(&mut self, pair: Pair, pending: Vec<OrderBookDiff>) {
+        metrics().resyncs.with_label_values(&[&symbol_label(pair)]).inc();
+        self.forget_diffs(pair);
+        self.orderbooks.insert(pair, None);
+        self.pending_diffs.insert(pair, pending);
+        self.record_levels(pair);
+        if let Some(resync_tx) = &self.resync_tx {
+            let _ = resync_tx.send(pair);
+        }
+    }
+
+    /// Live books that changed since they were last saved and are not being saved.
+    fn unsaved_books(&self) -> Vec<OrderBook> {
+        let mut books = Vec::new();
+        for (pair, orderbook) in self.orderbooks.iter() {
+            let Some(orderbook) = orderbook else {
+                continue;
+            };
+            if self.saves.contains_key(pair) || self.persisted_ids.get(pair) == Some(&orderbook.last_update_id) {
+                continue;
             }
+            books.push(orderbook.clone());
         }
-    });
+        books
+    }
+
+    /// Collects the saves that are done, or every save with `wait`, and remembers the
+    /// update ids of those that succeeded.
+    async fn finish_saves(&mut self, wait: bool) {
+        let done: Vec<Pair> = self.saves.iter().filter(|(_, (_, handle))| wait || handle.is_finished()).map(|(pair, _)| *pair).collect();
+        for pair in done {
+            let Some((last_update_id, handle)) = self.saves.remove(&pair) else {
+                continue;
+            };
+            if handle.await.unwrap_or_default() {
+                self.persisted_ids.insert(pair, last_update_id);
+            }
+        }
+    }
+
+    /// Writes every live book that changed since it was last saved. The writes happen
+    /// on the blocking pool so the manager keeps serving messages meanwhile. A book
+    /// whose previous save is still running is left for the next round.
+    async fn persist_books(&mut self) {
+        let Some(dir) = self.persistence.as_ref().map(|persistence| persistence.dir.clone()) else {
+            return;
+        };
+
+        self.finish_saves(false).await;
+        for orderbook in self.unsaved_books() {
+            let dir = dir.clone();
+            let (pair, last_update_id) = (orderbook.symbol, orderbook.last_update_id);
+            let handle = tokio::task::spawn_blocking(move || save_book(&dir, &orderbook));
+            self.saves.insert(pair, (last_update_id, handle));
+        }
+    }
+
+    /// Handles what is already queued in `rx` without taking anything new, waits for
+    /// running saves, then saves the live books a last time if the persistence settings
+    /// ask for it.
+    async fn shut_down(&mut self, rx: &mut mpsc::UnboundedReceiver<OrderbookMessage>) {
+        rx.close();
+        while let Some(msg) = rx.recv().await {
+            self.handle_message(msg);
+        }
+        self.finish_saves(true).await;
+
+        let Some(dir) = self.persistence.as_ref().filter(|persistence| persistence.save_on_shutdown).map(|persistence| persistence.dir.clone()) else {
+            return;
+        };
+        let books = self.unsaved_books();
+        let saved = tokio::task::spawn_blocking(move || books.iter().filter(|orderbook| save_book(&dir, orderbook)).count()).await;
+        info!(books = saved.unwrap_or_default(), "Saved books before shutting down");
+    }
+
+    fn orderbook(&self, pair: Pair) -> Result<&OrderBook, std::io::Error> {
+        match self.orderbooks.get(&pair) {
+            Some(Some(orderbook)) => Ok(orderbook),
+            Some(None) => Err(std::io::Error::other(format!("Orderbook for {:?} is not ready", pair))),
+            None => Err(std::io::Error::other(format!("Pair {:?} is not tracked", pair))),
+        }
+    }
+}
+
+/// Applies `diff` to `orderbook`, counting it as dropped when the book is already
+/// past it or it leaves a gap. Returns whether it was applied. Callers check for
+/// gaps first so they can resync.
+fn apply_diff(orderbook: &mut OrderBook, diff: OrderBookDiff) -> bool {
+    let symbol = symbol_label(orderbook.symbol);
+    let ahead = diff.last_update_id > orderbook.last_update_id;
+    if let Err(err) = orderbook.handle_diff(diff) {
+        warn!(symbol = ?orderbook.symbol, error = %err, "Dropping diff past a gap");
+        metrics().diffs_dropped.with_label_values(&[&symbol, "gap"]).inc();
+        return false;
+    }
+    if ahead {
+        metrics().diffs_applied.with_label_values(&[&symbol]).inc();
+    } else {
+        metrics().diffs_dropped.with_label_values(&[&symbol, "stale"]).inc();
+    }
+    ahead
+}
+
+/// Resolves on the next tick of `interval`, or never when there is none.
+pub async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// Saves `orderbook` under `dir`, logging a failure. Whether it was saved.
+fn save_book(dir: &std::path::Path, orderbook: &OrderBook) -> bool {
+    match save_orderbook(dir, orderbook) {
+        Ok(()) => true,
+        Err(err) => {
+            error!(symbol = ?orderbook.symbol, dir = %dir.display(), error = %err, "Failed to save book");
+            false
+        },
+    }
+}
+
+pub fn start_orderbook_manager(pairs: Vec<Pair>, rx: mpsc::UnboundedReceiver<OrderbookMessage>, resync_tx: mpsc::UnboundedSender<Pair>) -> JoinHandle<()> {
+    OrderbookManager::new(&pairs).with_resync_requests(resync_tx).spawn(rx)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{str::FromStr, time::Duration};
+
     use bigdecimal::BigDecimal;
+    use crate::time::now_micros;
     use crate::orderbook::OrderBookDepth;
 
-    use super::{OrderBook, OrderBookDiff, Pair};
+    use super::{
+        load_orderbook, BookState, InvariantConfig, InvariantReaction, OrderBook, OrderBookDiff, OrderbookManager, OrderbookMessage, Pair,
+        PersistenceConfig, VerificationConfig, DEFAULT_STALE_AFTER,
+    };
 
     #[test]
     fn test_bulk_values() {
@@ -243,7 +873,8 @@ mod tests {
             asks: asks_zero_in_between,
             first_update_id: 3,
             last_update_id: 4,
-        });
+            event_time: None,
+        }).unwrap();
         assert_eq!(orderbook.bids.len(), 1000);
         assert_eq!(orderbook.asks.len(), 1000);
 
@@ -255,7 +886,8 @@ mod tests {
             asks: asks_zero_half,
             first_update_id: 5,
             last_update_id: 6,
-        });
+            event_time: None,
+        }).unwrap();
         assert_eq!(orderbook.bids.len(), 500);
         assert_eq!(orderbook.asks.len(), 500);
 
@@ -267,7 +899,8 @@ mod tests {
             asks: asks_add,
             first_update_id: 7,
             last_update_id: 8,
-        });
+            event_time: None,
+        }).unwrap();
         assert_eq!(orderbook.bids.len(), 1750);
         assert_eq!(orderbook.asks.len(), 1750);
 
@@ -307,7 +940,8 @@ mod tests {
             asks: vec![(BigDecimal::from(1), BigDecimal::from(2)), (BigDecimal::from(2), BigDecimal::from(0))],
             first_update_id: 3,
             last_update_id: 7,
-        });
+            event_time: None,
+        }).unwrap();
 
         assert_eq!(orderbook.bids, vec![(BigDecimal::from(4), BigDecimal::from(5))]);
         assert_eq!(orderbook.asks, vec![(BigDecimal::from(1), BigDecimal::from(2))]);
@@ -318,16 +952,28 @@ mod tests {
             asks: vec![(BigDecimal::from(1), BigDecimal::from(3)), (BigDecimal::from(2), BigDecimal::from(3)), (BigDecimal::from(3), BigDecimal::from(4))],
             first_update_id: 8,
             last_update_id: 10,
-        });
+            event_time: None,
+        }).unwrap();
         
         assert_eq!(orderbook.bids, vec![(BigDecimal::from(6), BigDecimal::from(6)), (BigDecimal::from(5), BigDecimal::from(6)), (BigDecimal::from(4), BigDecimal::from(5)), (BigDecimal::from(3), BigDecimal::from(4))]);
         assert_eq!(orderbook.asks, vec![(BigDecimal::from(1), BigDecimal::from(3)), (BigDecimal::from(2), BigDecimal::from(3)), (BigDecimal::from(3), BigDecimal::from(4))]);
         assert_eq!(orderbook.last_update_id, 10);
+
+        // A diff carrying a single update
+        orderbook.handle_diff(OrderBookDiff {
+            bids: vec![(BigDecimal::from(3), BigDecimal::from(0))],
+            asks: vec![],
+            first_update_id: 11,
+            last_update_id: 11,
+            event_time: None,
+        }).unwrap();
+
+        assert_eq!(orderbook.bids.len(), 3);
+        assert_eq!(orderbook.last_update_id, 11);
     }
 
     #[test]
-    #[should_panic(expected = "Diff is too far ahead or too far behind")]
-    fn panic_not_consecutive_ids() {
+    fn rejects_non_consecutive_ids() {
         let bids = vec![(BigDecimal::from(5), BigDecimal::from(5)), (BigDecimal::from(4), BigDecimal::from(4))];
         let asks = vec![(BigDecimal::from(1), BigDecimal::from(1)), (BigDecimal::from(2), BigDecimal::from(2))];
 
@@ -342,6 +988,263 @@ mod tests {
             asks: vec![(BigDecimal::from(1), BigDecimal::from(2)), (BigDecimal::from(2), BigDecimal::from(0))],
             first_update_id: 4,
             last_update_id: 7,
+            event_time: None,
+        }).unwrap_err();
+
+        assert_eq!(orderbook.bids, vec![(BigDecimal::from(5), BigDecimal::from(5)), (BigDecimal::from(4), BigDecimal::from(4))]);
+        assert_eq!(orderbook.last_update_id, 2);
+    }
+
+    #[test]
+    fn manager_buffers_diffs_until_snapshot() {
+        let mut manager = OrderbookManager::new(&[Pair::BTCUSDT]);
+        assert!(manager.orderbook(Pair::BTCUSDT).is_err());
+        assert!(manager.orderbook(Pair::ETHUSDT).is_err());
+
+        // Arrives before the snapshot and is already covered by it
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::BTCUSDT, OrderBookDiff {
+            bids: vec![(BigDecimal::from(3), BigDecimal::from(3))],
+            asks: vec![],
+            first_update_id: 1,
+            last_update_id: 2,
+            event_time: None,
+        }));
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::BTCUSDT, OrderBookDiff {
+            bids: vec![(BigDecimal::from(5), BigDecimal::from(0))],
+            asks: vec![(BigDecimal::from(3), BigDecimal::from(3))],
+            first_update_id: 2,
+            last_update_id: 4,
+            event_time: None,
+        }));
+
+        let bids = vec![(BigDecimal::from(5), BigDecimal::from(5)), (BigDecimal::from(4), BigDecimal::from(4))];
+        let asks = vec![(BigDecimal::from(6), BigDecimal::from(1))];
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::BTCUSDT, bids, asks, 2)));
+
+        let orderbook = manager.orderbook(Pair::BTCUSDT).unwrap();
+        assert_eq!(orderbook.bids, vec![(BigDecimal::from(4), BigDecimal::from(4))]);
+        assert_eq!(orderbook.asks, vec![(BigDecimal::from(3), BigDecimal::from(3)), (BigDecimal::from(6), BigDecimal::from(1))]);
+        assert_eq!(orderbook.last_update_id, 4);
+
+        manager.handle_message(OrderbookMessage::Resync(Pair::BTCUSDT));
+        assert!(manager.orderbook(Pair::BTCUSDT).is_err());
+    }
+
+    #[actix_web::test]
+    async fn manager_saves_books_once_at_a_time() {
+        let dir = std::env::temp_dir().join(format!("challenge-manager-saves-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        // A file where the directory should be makes the first save fail
+        std::fs::write(&dir, b"").unwrap();
+        let mut manager = OrderbookManager::new(&[Pair::BTCUSDT]).with_persistence(PersistenceConfig {
+            dir: dir.clone(),
+            interval: Duration::from_secs(1),
+            max_age: Duration::from_secs(60),
+            save_on_shutdown: false,
         });
+        let bids = vec![(BigDecimal::from(5), BigDecimal::from(5))];
+        let asks = vec![(BigDecimal::from(6), BigDecimal::from(1))];
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::BTCUSDT, bids, asks, 2)));
+
+        manager.persist_books().await;
+        // Not saved again while the first save is running
+        assert!(manager.unsaved_books().is_empty());
+        manager.finish_saves(true).await;
+        assert!(manager.persisted_ids.is_empty());
+
+        // The failed save is retried on the next round
+        std::fs::remove_file(&dir).unwrap();
+        manager.persist_books().await;
+        manager.finish_saves(true).await;
+        assert_eq!(manager.persisted_ids.get(&Pair::BTCUSDT), Some(&2));
+        assert_eq!(load_orderbook(&dir, Pair::BTCUSDT, Duration::from_secs(60)).unwrap().unwrap().last_update_id, 2);
+        assert!(manager.unsaved_books().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manager_requests_resync_on_gaps() {
+        let (resync_tx, mut resync_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut manager = OrderbookManager::new(&[Pair::ETHUSDT]).with_resync_requests(resync_tx);
+
+        let bids = vec![(BigDecimal::from(5), BigDecimal::from(5))];
+        let asks = vec![(BigDecimal::from(6), BigDecimal::from(1))];
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::ETHUSDT, bids.clone(), asks.clone(), 10)));
+        assert!(resync_rx.try_recv().is_err());
+
+        // Skips update ids 11 to 14
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::ETHUSDT, OrderBookDiff {
+            bids: vec![(BigDecimal::from(4), BigDecimal::from(4))],
+            asks: vec![],
+            first_update_id: 15,
+            last_update_id: 16,
+            event_time: None,
+        }));
+        assert_eq!(resync_rx.try_recv().unwrap(), Pair::ETHUSDT);
+        assert!(manager.orderbook(Pair::ETHUSDT).is_err());
+
+        // A snapshot older than the buffered diff is not enough
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::ETHUSDT, bids.clone(), asks.clone(), 12)));
+        assert_eq!(resync_rx.try_recv().unwrap(), Pair::ETHUSDT);
+        assert!(manager.orderbook(Pair::ETHUSDT).is_err());
+
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::ETHUSDT, bids, asks, 14)));
+        assert!(resync_rx.try_recv().is_err());
+        let orderbook = manager.orderbook(Pair::ETHUSDT).unwrap();
+        assert_eq!(orderbook.bids, vec![(BigDecimal::from(5), BigDecimal::from(5)), (BigDecimal::from(4), BigDecimal::from(4))]);
+        assert_eq!(orderbook.last_update_id, 16);
+    }
+
+    #[test]
+    fn manager_reports_book_status() {
+        let mut manager = OrderbookManager::new(&[Pair::ETHUSDT, Pair::BTCUSDT]);
+        let now = now_micros();
+        let states = |manager: &OrderbookManager, now: u64| manager.status(now).iter().map(|status| status.state).collect::<Vec<BookState>>();
+        assert_eq!(states(&manager, now), vec![BookState::Bootstrapping, BookState::Bootstrapping]);
+
+        let bids = vec![(BigDecimal::from(5), BigDecimal::from(5))];
+        let asks = vec![(BigDecimal::from(6), BigDecimal::from(1))];
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::ETHUSDT, bids, asks, 10)));
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::ETHUSDT, OrderBookDiff {
+            bids: vec![(BigDecimal::from(4), BigDecimal::from(4))],
+            asks: vec![],
+            first_update_id: 11,
+            last_update_id: 12,
+            event_time: Some(now - 250_000),
+        }));
+
+        let now = now_micros();
+        let status = manager.status(now);
+        assert_eq!(status[0].symbol, Pair::BTCUSDT);
+        assert_eq!(status[1].state, BookState::Live);
+        assert_eq!(status[1].last_update_id, Some(12));
+        assert!(status[1].last_diff_age.is_some());
+        assert!(status[1].last_event_age.is_some_and(|age| age.as_millis() >= 250));
+        assert_eq!(status[0].last_update_id, None);
+
+        // Nothing applied for longer than the threshold
+        let later = now + DEFAULT_STALE_AFTER.as_micros() as u64 + 1_000_000;
+        assert_eq!(states(&manager, later), vec![BookState::Bootstrapping, BookState::Stale]);
+        manager = manager.with_stale_after(DEFAULT_STALE_AFTER * 2);
+        assert_eq!(states(&manager, later), vec![BookState::Bootstrapping, BookState::Live]);
+
+        manager.handle_message(OrderbookMessage::Resync(Pair::ETHUSDT));
+        assert_eq!(states(&manager, now), vec![BookState::Bootstrapping, BookState::Resyncing]);
+    }
+
+    #[test]
+    fn manager_verifies_books_against_snapshots() {
+        let (resync_tx, mut resync_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut manager = OrderbookManager::new(&[Pair::ETHUSDT])
+            .with_resync_requests(resync_tx)
+            .with_verification(VerificationConfig { interval: Duration::from_secs(60), resync_on_mismatch: true });
+        let level = |price: u32, quantity: u32| (BigDecimal::from(price), BigDecimal::from(quantity));
+        let diff = |first_update_id, last_update_id, bids| OrderBookDiff { bids, asks: vec![], first_update_id, last_update_id, event_time: None };
+
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::ETHUSDT, vec![level(5, 5)], vec![level(6, 1)], 10)));
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::ETHUSDT, diff(11, 12, vec![level(4, 4)])));
+
+        // Behind the book, brought up to it with the diffs applied since
+        manager.handle_message(OrderbookMessage::Verify(OrderBook::new(Pair::ETHUSDT, vec![level(5, 5)], vec![level(6, 1)], 10)));
+        assert!(resync_rx.try_recv().is_err());
+
+        // Ahead of the book and wrong, only found out once the book gets there
+        manager.handle_message(OrderbookMessage::Verify(OrderBook::new(Pair::ETHUSDT, vec![level(5, 1), level(4, 4)], vec![level(6, 1)], 14)));
+        assert!(resync_rx.try_recv().is_err());
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::ETHUSDT, diff(13, 14, vec![level(3, 3)])));
+        assert_eq!(resync_rx.try_recv().unwrap(), Pair::ETHUSDT);
+        assert!(manager.orderbook(Pair::ETHUSDT).is_err());
+    }
+
+    #[test]
+    fn manager_reacts_to_broken_invariants() {
+        let (resync_tx, mut resync_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut manager = OrderbookManager::new(&[Pair::BTCUSDT])
+            .with_resync_requests(resync_tx)
+            .with_invariants(InvariantConfig { sample_every: 1, check_every_diff: false, reaction: InvariantReaction::Resync });
+
+        let bids = vec![(BigDecimal::from(5), BigDecimal::from(5))];
+        let asks = vec![(BigDecimal::from(6), BigDecimal::from(1))];
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::BTCUSDT, bids, asks, 10)));
+        assert!(resync_rx.try_recv().is_err());
+
+        // A bid above the best ask crosses the book
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::BTCUSDT, OrderBookDiff {
+            bids: vec![(BigDecimal::from(7), BigDecimal::from(1))],
+            asks: vec![],
+            first_update_id: 11,
+            last_update_id: 11,
+            event_time: None,
+        }));
+        assert_eq!(resync_rx.try_recv().unwrap(), Pair::BTCUSDT);
+        assert!(manager.orderbook(Pair::BTCUSDT).is_err());
+    }
+
+    #[test]
+    fn manager_warm_starts_from_saved_books() {
+        let (resync_tx, mut resync_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut manager = OrderbookManager::new(&[Pair::BTCUSDT, Pair::ETHUSDT]).with_resync_requests(resync_tx);
+        let diff = |first_update_id, last_update_id| OrderBookDiff {
+            bids: vec![(BigDecimal::from(4), BigDecimal::from(4))],
+            asks: vec![],
+            first_update_id,
+            last_update_id,
+            event_time: None,
+        };
+        let saved_book = |pair| OrderBook::new(pair, vec![(BigDecimal::from(5), BigDecimal::from(5))], vec![(BigDecimal::from(6), BigDecimal::from(1))], 10);
+
+        // Diffs buffered before the saved book is loaded are replayed on top of it
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::BTCUSDT, diff(8, 9)));
+        manager.handle_message(OrderbookMessage::WarmStart(saved_book(Pair::BTCUSDT)));
+        assert!(manager.orderbook(Pair::BTCUSDT).is_err());
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::BTCUSDT, diff(9, 12)));
+        assert_eq!(manager.orderbook(Pair::BTCUSDT).unwrap().last_update_id, 12);
+        assert!(resync_rx.try_recv().is_err());
+
+        // The stream moved on since the book was saved
+        manager.handle_message(OrderbookMessage::WarmStart(saved_book(Pair::ETHUSDT)));
+        manager.handle_message(OrderbookMessage::OrderbookDiff(Pair::ETHUSDT, diff(20, 22)));
+        assert_eq!(resync_rx.try_recv().unwrap(), Pair::ETHUSDT);
+        assert!(manager.orderbook(Pair::ETHUSDT).is_err());
+
+        manager.handle_message(OrderbookMessage::Snapshot(OrderBook::new(Pair::ETHUSDT, vec![], vec![], 19)));
+        assert_eq!(manager.orderbook(Pair::ETHUSDT).unwrap().last_update_id, 22);
+    }
+
+    #[test]
+    fn serializes_books_and_diffs() {
+        let level = |price: &str, quantity: &str| (BigDecimal::from_str(price).unwrap(), BigDecimal::from_str(quantity).unwrap());
+        let orderbook = OrderBook::new(
+            Pair::ETHUSDT,
+            vec![level("3000.10", "1.50000000"), level("3000.00", "0.00000001")],
+            vec![level("3000.20", "2")],
+            123456789,
+        );
+
+        let json = serde_json::to_value(&orderbook).unwrap();
+        assert_eq!(json["symbol"], "ETHUSDT");
+        assert_eq!(json["bids"][0], serde_json::json!(["3000.10", "1.50000000"]));
+        let decoded: OrderBook = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.symbol(), Pair::ETHUSDT);
+        assert_eq!(decoded.bids(), orderbook.bids());
+        assert_eq!(decoded.last_update_id(), 123456789);
+
+        let bytes = orderbook.to_binary().unwrap();
+        assert!(bytes.len() < serde_json::to_vec(&orderbook).unwrap().len());
+        let decoded = OrderBook::from_binary(&bytes).unwrap();
+        assert_eq!(decoded.asks(), orderbook.asks());
+        assert_eq!(decoded.last_update_id(), orderbook.last_update_id());
+        assert!(OrderBook::from_binary(&bytes[..bytes.len() - 1]).is_err());
+
+        let diff = OrderBookDiff {
+            bids: vec![level("3000.10", "0")],
+            asks: vec![],
+            first_update_id: 123456790,
+            last_update_id: 123456792,
+            event_time: Some(1712345678901000),
+        };
+        assert_eq!(OrderBookDiff::from_binary(&diff.to_binary().unwrap()).unwrap(), diff);
+        assert_eq!(serde_json::from_str::<OrderBookDiff>(&serde_json::to_string(&diff).unwrap()).unwrap(), diff);
     }
 }
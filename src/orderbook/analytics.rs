@@ -0,0 +1,154 @@
+use std::io::Error;
+
+use bigdecimal::{BigDecimal, Zero};
+
+use super::{OrderBook, OrderBookDepth};
+
+/// Decimal places kept on quotients that do not terminate, such as averages and ratios.
+pub const QUOTIENT_SCALE: i64 = 10;
+
+/// `numerator / denominator`, rounded to [`QUOTIENT_SCALE`] places when longer.
+pub fn divide(numerator: &BigDecimal, denominator: &BigDecimal) -> BigDecimal {
+    let quotient = numerator / denominator;
+    if quotient.as_bigint_and_exponent().1 > QUOTIENT_SCALE {
+        quotient.round(QUOTIENT_SCALE)
+    } else {
+        quotient
+    }
+}
+
+/// `(bid - ask) / (bid + ask)`, from -1 (only asks) to 1 (only bids), 0 when both are empty.
+pub fn imbalance(bid_quantity: &BigDecimal, ask_quantity: &BigDecimal) -> BigDecimal {
+    let total = bid_quantity + ask_quantity;
+    if total.is_zero() {
+        return BigDecimal::zero();
+    }
+    divide(&(bid_quantity - ask_quantity), &total)
+}
+
+/// Resting liquidity within a band around mid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BandDepth {
+    pub bid_quantity: BigDecimal,
+    /// Sum of price times quantity.
+    pub bid_notional: BigDecimal,
+    pub ask_quantity: BigDecimal,
+    pub ask_notional: BigDecimal,
+}
+
+impl BandDepth {
+    pub fn imbalance(&self) -> BigDecimal {
+        imbalance(&self.bid_quantity, &self.ask_quantity)
+    }
+}
+
+fn sum_levels<'a>(levels: impl Iterator<Item = &'a (BigDecimal, BigDecimal)>) -> (BigDecimal, BigDecimal) {
+    levels.fold((BigDecimal::zero(), BigDecimal::zero()), |(quantity, notional), (price, amount)| {
+        (quantity + amount, notional + price * amount)
+    })
+}
+
+fn top_quantity(levels: &OrderBookDepth, count: usize) -> BigDecimal {
+    levels.iter().take(count).map(|(_, quantity)| quantity).sum()
+}
+
+impl OrderBook {
+    pub fn mid(&self) -> Result<BigDecimal, Error> {
+        let ((bid, _), (ask, _)) = self.get_tips()?;
+        Ok((bid + ask) / BigDecimal::from(2))
+    }
+
+    pub fn spread(&self) -> Result<BigDecimal, Error> {
+        let ((bid, _), (ask, _)) = self.get_tips()?;
+        Ok(ask - bid)
+    }
+
+    /// Spread relative to mid, in basis points.
+    pub fn spread_bps(&self) -> Result<BigDecimal, Error> {
+        Ok(divide(&(self.spread()? * BigDecimal::from(10_000)), &self.mid()?))
+    }
+
+    /// Mid weighted by the opposite side's best quantity, leaning towards the side
+    /// more likely to move next.
+    pub fn microprice(&self) -> Result<BigDecimal, Error> {
+        let ((bid, bid_quantity), (ask, ask_quantity)) = self.get_tips()?;
+        Ok(divide(&(&bid * &ask_quantity + &ask * &bid_quantity), &(bid_quantity + ask_quantity)))
+    }
+
+    /// Quantity imbalance over the best `levels` levels of each side.
+    pub fn imbalance(&self, levels: usize) -> BigDecimal {
+        imbalance(&top_quantity(&self.bids, levels), &top_quantity(&self.asks, levels))
+    }
+
+    /// Liquidity priced within `bps` basis points of mid on each side.
+    pub fn depth_within_bps(&self, bps: &BigDecimal) -> Result<BandDepth, Error> {
+        let mid = self.mid()?;
+        let offset = &mid * bps / BigDecimal::from(10_000);
+        let (bid_floor, ask_ceiling) = (&mid - &offset, &mid + &offset);
+
+        let (bid_quantity, bid_notional) = sum_levels(self.bids.iter().take_while(|(price, _)| *price >= bid_floor));
+        let (ask_quantity, ask_notional) = sum_levels(self.asks.iter().take_while(|(price, _)| *price <= ask_ceiling));
+        Ok(BandDepth {
+            bid_quantity,
+            bid_notional,
+            ask_quantity,
+            ask_notional,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::orderbook::{OrderBook, Pair};
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    fn book() -> OrderBook {
+        OrderBook::new(
+            Pair::BTCUSDT,
+            vec![(decimal("99.99"), decimal("3")), (decimal("99.95"), decimal("1")), (decimal("99.00"), decimal("10"))],
+            vec![(decimal("100.01"), decimal("1")), (decimal("100.04"), decimal("2")), (decimal("101.00"), decimal("5"))],
+            1,
+        )
+    }
+
+    #[test]
+    fn prices_and_spread() {
+        let orderbook = book();
+        assert_eq!(orderbook.mid().unwrap(), decimal("100"));
+        assert_eq!(orderbook.spread().unwrap(), decimal("0.02"));
+        assert_eq!(orderbook.spread_bps().unwrap(), decimal("2"));
+        // 3 to 1 on the bid, so the microprice leans towards the ask
+        assert_eq!(orderbook.microprice().unwrap(), decimal("100.005"));
+
+        let empty = OrderBook::new(Pair::BTCUSDT, vec![], vec![(decimal("1"), decimal("1"))], 1);
+        assert!(empty.mid().is_err());
+        assert!(empty.microprice().is_err());
+    }
+
+    #[test]
+    fn imbalance_and_band_depth() {
+        let orderbook = book();
+        assert_eq!(orderbook.imbalance(1), decimal("0.5"));
+        assert_eq!(orderbook.imbalance(2), decimal("0.1428571429"));
+        assert_eq!(orderbook.imbalance(0), decimal("0"));
+
+        // 5 bps of 100 is 0.05
+        let depth = orderbook.depth_within_bps(&decimal("5")).unwrap();
+        assert_eq!(depth.bid_quantity, decimal("4"));
+        assert_eq!(depth.bid_notional, decimal("399.92"));
+        assert_eq!(depth.ask_quantity, decimal("3"));
+        assert_eq!(depth.ask_notional, decimal("300.09"));
+        assert_eq!(depth.imbalance(), decimal("0.1428571429"));
+
+        let depth = orderbook.depth_within_bps(&decimal("200")).unwrap();
+        assert_eq!(depth.bid_quantity, decimal("14"));
+        assert_eq!(depth.ask_quantity, decimal("8"));
+    }
+}
@@ -0,0 +1,198 @@
+use std::io::{Error, ErrorKind};
+
+use bigdecimal::{BigDecimal, Zero};
+
+use super::{divide, OrderBook, OrderBookDepth};
+
+/// Liquidity from the best price up to and including `price`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthPoint {
+    pub price: BigDecimal,
+    pub quantity: BigDecimal,
+    /// Sum of price times quantity.
+    pub notional: BigDecimal,
+}
+
+/// Cumulative depth of both sides, best price first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthChart {
+    pub bids: Vec<DepthPoint>,
+    pub asks: Vec<DepthPoint>,
+}
+
+/// Merges `levels` into buckets `tick` wide. Levels are ordered best first, so each
+/// bucket is a run of consecutive levels.
+fn group_levels(levels: &OrderBookDepth, tick: &BigDecimal, round_up: bool) -> OrderBookDepth {
+    let mut grouped: OrderBookDepth = Vec::new();
+    for (price, quantity) in levels {
+        let remainder = price % tick;
+        let bucket = if remainder.is_zero() {
+            price.clone()
+        } else if round_up {
+            price - remainder + tick
+        } else {
+            price - remainder
+        };
+
+        match grouped.last_mut() {
+            Some((last, total)) if *last == bucket => *total += quantity,
+            _ => grouped.push((bucket, quantity.clone())),
+        }
+    }
+    grouped
+}
+
+/// Cumulative depth over `levels`, one point per level when there are at most `points`
+/// of them and otherwise sampled at `points` evenly spaced prices from the best level
+/// to `end`, or to the last level.
+fn cumulative(levels: &[(BigDecimal, BigDecimal)], points: usize, end: Option<&BigDecimal>, ascending: bool) -> Vec<DepthPoint> {
+    let within = |price: &BigDecimal, bound: &BigDecimal| if ascending { price <= bound } else { price >= bound };
+    let mut quantity = BigDecimal::zero();
+    let mut notional = BigDecimal::zero();
+
+    if levels.len() <= points {
+        return levels
+            .iter()
+            .map(|(price, amount)| {
+                quantity += amount;
+                notional += price * amount;
+                DepthPoint {
+                    price: price.clone(),
+                    quantity: quantity.clone(),
+                    notional: notional.clone(),
+                }
+            })
+            .collect();
+    }
+
+    let best = &levels[0].0;
+    let end = end.unwrap_or(&levels[levels.len() - 1].0);
+    let steps = BigDecimal::from(points.saturating_sub(1).max(1) as u64);
+    let mut levels = levels.iter().peekable();
+    (0..points)
+        .map(|step| {
+            let price = if step + 1 == points { end.clone() } else { best + divide(&((end - best) * BigDecimal::from(step as u64)), &steps) };
+            while let Some((level, amount)) = levels.peek() {
+                if !within(level, &price) {
+                    break;
+                }
+                quantity += amount;
+                notional += level * amount;
+                levels.next();
+            }
+            DepthPoint {
+                price,
+                quantity: quantity.clone(),
+                notional: notional.clone(),
+            }
+        })
+        .collect()
+}
+
+impl OrderBook {
+    /// Cumulative depth of each side in at most `points` points. With `range_pct`, only
+    /// levels within that percentage of mid are included and the sampled prices span
+    /// the whole range.
+    pub fn depth_chart(&self, points: usize, range_pct: Option<&BigDecimal>) -> Result<DepthChart, Error> {
+        if points == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "At least one point is needed"));
+        }
+        let (bid_floor, ask_ceiling) = match range_pct {
+            Some(pct) if *pct <= BigDecimal::zero() => return Err(Error::new(ErrorKind::InvalidInput, "Range must be positive")),
+            Some(pct) => {
+                let mid = self.mid()?;
+                let offset = &mid * pct / BigDecimal::from(100);
+                (Some(&mid - &offset), Some(&mid + offset))
+            },
+            None => (None, None),
+        };
+
+        let bid_count = bid_floor.as_ref().map_or(self.bids.len(), |floor| self.bids.iter().take_while(|(price, _)| price >= floor).count());
+        let ask_count = ask_ceiling.as_ref().map_or(self.asks.len(), |ceiling| self.asks.iter().take_while(|(price, _)| price <= ceiling).count());
+        Ok(DepthChart {
+            bids: cumulative(&self.bids[..bid_count], points, bid_floor.as_ref(), false),
+            asks: cumulative(&self.asks[..ask_count], points, ask_ceiling.as_ref(), true),
+        })
+    }
+
+    /// The book with its levels merged into price buckets `tick` wide. Bids are
+    /// rounded down and asks up, so a bucket never looks better than its levels.
+    pub fn grouped(&self, tick: &BigDecimal) -> Result<OrderBook, Error> {
+        if *tick <= BigDecimal::zero() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Tick must be positive"));
+        }
+        Ok(OrderBook {
+            symbol: self.symbol,
+            bids: group_levels(&self.bids, tick, false),
+            asks: group_levels(&self.asks, tick, true),
+            last_update_id: self.last_update_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::orderbook::{OrderBook, Pair};
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn groups_into_buckets() {
+        let orderbook = OrderBook::new(
+            Pair::BTCUSDT,
+            vec![(decimal("60015.5"), decimal("1")), (decimal("60010"), decimal("2")), (decimal("60009.99"), decimal("0.5"))],
+            vec![(decimal("60016"), decimal("1")), (decimal("60020"), decimal("1.5")), (decimal("60020.01"), decimal("3"))],
+            7,
+        );
+
+        let grouped = orderbook.grouped(&decimal("10")).unwrap();
+        assert_eq!(grouped.last_update_id(), 7);
+        assert_eq!(grouped.bids(), &vec![(decimal("60010"), decimal("3")), (decimal("60000"), decimal("0.5"))]);
+        assert_eq!(grouped.asks(), &vec![(decimal("60020"), decimal("2.5")), (decimal("60030"), decimal("3"))]);
+
+        let grouped = orderbook.grouped(&decimal("0.5")).unwrap();
+        assert_eq!(grouped.bids()[0], (decimal("60015.5"), decimal("1")));
+        assert_eq!(grouped.asks()[2], (decimal("60020.5"), decimal("3")));
+
+        assert!(orderbook.grouped(&decimal("0")).is_err());
+    }
+
+    #[test]
+    fn cumulative_depth_chart() {
+        let orderbook = OrderBook::new(
+            Pair::BTCUSDT,
+            vec![(decimal("99"), decimal("1")), (decimal("98"), decimal("2")), (decimal("90"), decimal("5"))],
+            vec![(decimal("101"), decimal("1")), (decimal("102"), decimal("1")), (decimal("103"), decimal("1")), (decimal("110"), decimal("4"))],
+            1,
+        );
+
+        // Few enough levels for one point each
+        let chart = orderbook.depth_chart(10, None).unwrap();
+        assert_eq!(chart.bids.len(), 3);
+        assert_eq!(chart.bids[1].quantity, decimal("3"));
+        assert_eq!(chart.bids[1].notional, decimal("295"));
+        assert_eq!(chart.asks[3].quantity, decimal("7"));
+
+        // Sampled from the best to the last level
+        let chart = orderbook.depth_chart(3, None).unwrap();
+        assert_eq!(chart.asks.iter().map(|point| point.price.clone()).collect::<Vec<BigDecimal>>(), vec![decimal("101"), decimal("105.5"), decimal("110")]);
+        assert_eq!(chart.asks.iter().map(|point| point.quantity.clone()).collect::<Vec<BigDecimal>>(), vec![decimal("1"), decimal("3"), decimal("7")]);
+        assert_eq!(chart.asks[1].notional, decimal("306"));
+
+        // 5% of a mid of 100 leaves out the deepest level of each side
+        let chart = orderbook.depth_chart(2, Some(&decimal("5"))).unwrap();
+        assert_eq!(chart.bids.len(), 2);
+        assert_eq!(chart.bids[1].quantity, decimal("3"));
+        assert_eq!(chart.asks.iter().map(|point| point.price.clone()).collect::<Vec<BigDecimal>>(), vec![decimal("101"), decimal("105")]);
+        assert_eq!(chart.asks[1].quantity, decimal("3"));
+
+        assert!(orderbook.depth_chart(0, None).is_err());
+        assert!(OrderBook::new(Pair::BTCUSDT, vec![], vec![], 1).depth_chart(5, Some(&decimal("1"))).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use std::io::{Error, ErrorKind};
+
+use bigdecimal::{BigDecimal, RoundingMode, Zero};
+use serde::{Deserialize, Serialize};
+
+use super::{divide, OrderBook, OrderBookDepth, QUOTIENT_SCALE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    /// Takes liquidity from the asks.
+    Buy,
+    /// Takes liquidity from the bids.
+    Sell,
+}
+
+/// Outcome of taking `quantity` from one side of the book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub quantity: BigDecimal,
+    /// Less than `quantity` when the book runs out of levels.
+    pub filled: BigDecimal,
+    /// Quote amount paid or received.
+    pub notional: BigDecimal,
+    pub average_price: BigDecimal,
+    /// Price of the last level touched.
+    pub worst_price: BigDecimal,
+    /// Distance of the average price from the best price, in basis points. Always
+    /// positive as it is a cost for both sides.
+    pub slippage_bps: BigDecimal,
+}
+
+impl Fill {
+    pub fn is_complete(&self) -> bool {
+        self.filled == self.quantity
+    }
+}
+
+/// Bound on how deep a fill may go.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FillLimit {
+    /// The average price may not be further than this from the best price, in basis points.
+    SlippageBps(BigDecimal),
+    /// No level priced worse than this is touched.
+    WorstPrice(BigDecimal),
+}
+
+/// Rounds to [`QUOTIENT_SCALE`] places in the given direction, so partial levels never
+/// overshoot a limit or overstate what a fill gets.
+fn round_towards(value: BigDecimal, mode: RoundingMode) -> BigDecimal {
+    if value.as_bigint_and_exponent().1 > QUOTIENT_SCALE {
+        value.with_scale_round(QUOTIENT_SCALE, mode)
+    } else {
+        value
+    }
+}
+
+/// Slippage of `price` against `best`, positive when `price` is worse for `side`.
+pub fn slippage_bps(side: Side, best: &BigDecimal, price: &BigDecimal) -> BigDecimal {
+    let difference = match side {
+        Side::Buy => price - best,
+        Side::Sell => best - price,
+    };
+    divide(&(difference * BigDecimal::from(10_000)), best)
+}
+
+impl OrderBook {
+    /// Levels a `side` order takes from, best first.
+    pub fn levels_for(&self, side: Side) -> &OrderBookDepth {
+        match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        }
+    }
+
+    /// Best price a `side` order gets, erroring when that side is empty.
+    fn best_price(&self, side: Side) -> Result<BigDecimal, Error> {
+        match (self.levels_for(side).first(), side) {
+            (Some((price, _)), _) => Ok(price.clone()),
+            (None, Side::Buy) => Err(Error::other("No asks")),
+            (None, Side::Sell) => Err(Error::other("No bids")),
+        }
+    }
+
+    /// Fills of each of `quantities` against the current book, in the order given.
+    /// Levels are walked once however many quantities are asked for.
+    pub fn impact_curve(&self, side: Side, quantities: &[BigDecimal]) -> Result<Vec<Fill>, Error> {
+        let levels = self.levels_for(side);
+        let best = self.best_price(side)?;
+        if quantities.iter().any(|quantity| *quantity <= BigDecimal::zero()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Quantities must be positive"));
+        }
+
+        let mut order = (0..quantities.len()).collect::<Vec<usize>>();
+        order.sort_by(|a, b| quantities[*a].cmp(&quantities[*b]));
+
+        let mut fills: Vec<Option<Fill>> = vec![None; quantities.len()];
+        let mut levels = levels.iter().peekable();
+        // Totals over the levels fully consumed so far
+        let mut taken = BigDecimal::zero();
+        let mut paid = BigDecimal::zero();
+        let mut last_price = best.clone();
+
+        for index in order {
+            let quantity = &quantities[index];
+            // Consume whole levels that fit below this quantity
+            while let Some((price, amount)) = levels.peek() {
+                if &taken + amount > *quantity {
+                    break;
+                }
+                taken += amount;
+                paid += price * amount;
+                last_price = price.clone();
+                levels.next();
+            }
+
+            let (filled, notional, worst_price) = match levels.peek() {
+                Some((price, _)) if taken < *quantity => {
+                    let rest = quantity - &taken;
+                    (quantity.clone(), &paid + price * rest, price.clone())
+                },
+                _ => (taken.clone(), paid.clone(), last_price.clone()),
+            };
+            let average_price = divide(&notional, &filled);
+            fills[index] = Some(Fill {
+                quantity: quantity.clone(),
+                slippage_bps: slippage_bps(side, &best, &average_price),
+                filled,
+                notional,
+                average_price,
+                worst_price,
+            });
+        }
+
+        Ok(fills.into_iter().flatten().collect())
+    }
+
+    /// The largest fill on `side` that stays within `limit`. Its `quantity` and
+    /// `notional` are the base and quote amounts that can be traded, both zero when
+    /// not even part of the best level fits.
+    pub fn max_fill(&self, side: Side, limit: &FillLimit) -> Result<Fill, Error> {
+        let levels = self.levels_for(side);
+        let best = self.best_price(side)?;
+        // Whether a price is no worse than `bound` for this side
+        let within = |price: &BigDecimal, bound: &BigDecimal| match side {
+            Side::Buy => price <= bound,
+            Side::Sell => price >= bound,
+        };
+
+        let mut taken = BigDecimal::zero();
+        let mut paid = BigDecimal::zero();
+        let mut worst_price = best.clone();
+        match limit {
+            FillLimit::WorstPrice(bound) => {
+                for (price, amount) in levels.iter().take_while(|(price, _)| within(price, bound)) {
+                    taken += amount;
+                    paid += price * amount;
+                    worst_price = price.clone();
+                }
+            },
+            FillLimit::SlippageBps(bps) => {
+                if *bps < BigDecimal::zero() {
+                    return Err(Error::new(ErrorKind::InvalidInput, "Slippage must not be negative"));
+                }
+                let offset = &best * bps / BigDecimal::from(10_000);
+                let average_bound = match side {
+                    Side::Buy => &best + offset,
+                    Side::Sell => &best - offset,
+                };
+                for (price, amount) in levels.iter() {
+                    if within(price, &average_bound) {
+                        taken += amount;
+                        paid += price * amount;
+                        worst_price = price.clone();
+                        continue;
+                    }
+                    // Part of this level brings the average exactly to the bound:
+                    // (paid + price * x) / (taken + x) = bound
+                    let partial = round_towards((&average_bound * &taken - &paid) / (price - &average_bound), RoundingMode::Down).min(amount.clone());
+                    if partial > BigDecimal::zero() {
+                        paid += price * &partial;
+                        taken += partial;
+                        worst_price = price.clone();
+                    }
+                    break;
+                }
+            },
+        }
+
+        let average_price = if taken.is_zero() { best.clone() } else { divide(&paid, &taken) };
+        Ok(Fill {
+            quantity: taken.clone(),
+            filled: taken,
+            slippage_bps: slippage_bps(side, &best, &average_price),
+            notional: paid,
+            average_price,
+            worst_price,
+        })
+    }
+
+    /// Fill spending (`Buy`) or raising (`Sell`) `notional` of the quote asset. `quantity`
+    /// and `filled` are the base amount traded, and `notional` falls short of the one
+    /// asked for when the book runs out.
+    pub fn execution_for_notional(&self, side: Side, notional: &BigDecimal) -> Result<Fill, Error> {
+        let best = self.best_price(side)?;
+        if *notional <= BigDecimal::zero() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Notional must be positive"));
+        }
+
+        let mut taken = BigDecimal::zero();
+        let mut paid = BigDecimal::zero();
+        let mut worst_price = best.clone();
+        for (price, amount) in self.levels_for(side).iter() {
+            let level_notional = price * amount;
+            worst_price = price.clone();
+            if &paid + &level_notional >= *notional {
+                // Buying gets no more base than the notional pays for, selling gives up at least enough
+                let mode = match side {
+                    Side::Buy => RoundingMode::Down,
+                    Side::Sell => RoundingMode::Up,
+                };
+                taken += round_towards((notional - &paid) / price, mode);
+                paid = notional.clone();
+                break;
+            }
+            taken += amount;
+            paid += level_notional;
+        }
+
+        let average_price = if taken.is_zero() { best.clone() } else { divide(&paid, &taken) };
+        Ok(Fill {
+            quantity: taken.clone(),
+            filled: taken,
+            slippage_bps: slippage_bps(side, &best, &average_price),
+            notional: paid,
+            average_price,
+            worst_price,
+        })
+    }
+
+    /// Fill of a single `quantity`, see [`OrderBook::impact_curve`].
+    pub fn execution(&self, side: Side, quantity: &BigDecimal) -> Result<Fill, Error> {
+        let mut fills = self.impact_curve(side, std::slice::from_ref(quantity))?;
+        fills.pop().ok_or_else(|| Error::other("No fill computed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use super::{FillLimit, Side};
+    use crate::orderbook::{OrderBook, Pair};
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    fn book() -> OrderBook {
+        OrderBook::new(
+            Pair::BTCUSDT,
+            vec![(decimal("99"), decimal("1")), (decimal("98"), decimal("2"))],
+            vec![(decimal("100"), decimal("1")), (decimal("101"), decimal("1")), (decimal("104"), decimal("2"))],
+            1,
+        )
+    }
+
+    #[test]
+    fn walks_levels_once_for_every_size() {
+        let orderbook = book();
+        let sizes = [decimal("2"), decimal("0.5"), decimal("3"), decimal("10"), decimal("1")];
+        let fills = orderbook.impact_curve(Side::Buy, &sizes).unwrap();
+
+        // Results follow the order of the request
+        assert_eq!(fills.iter().map(|fill| fill.quantity.clone()).collect::<Vec<BigDecimal>>(), sizes.to_vec());
+
+        assert_eq!(fills[1].average_price, decimal("100"));
+        assert_eq!(fills[1].slippage_bps, decimal("0"));
+        assert_eq!(fills[4].worst_price, decimal("100"));
+        assert_eq!(fills[0].average_price, decimal("100.5"));
+        assert_eq!(fills[0].slippage_bps, decimal("50"));
+        assert_eq!(fills[0].worst_price, decimal("101"));
+        assert_eq!(fills[2].notional, decimal("305"));
+        assert_eq!(fills[2].average_price, decimal("101.6666666667"));
+        assert!(fills[2].is_complete());
+
+        // More than the book holds
+        assert!(!fills[3].is_complete());
+        assert_eq!(fills[3].filled, decimal("4"));
+        assert_eq!(fills[3].notional, decimal("409"));
+        assert_eq!(fills[3].worst_price, decimal("104"));
+    }
+
+    #[test]
+    fn sells_into_bids() {
+        let orderbook = book();
+        let fill = orderbook.execution(Side::Sell, &decimal("2")).unwrap();
+        assert_eq!(fill.average_price, decimal("98.5"));
+        assert_eq!(fill.slippage_bps, decimal("50.5050505051"));
+
+        assert!(orderbook.execution(Side::Sell, &decimal("0")).is_err());
+        assert!(OrderBook::new(Pair::BTCUSDT, vec![], vec![], 1).execution(Side::Buy, &decimal("1")).is_err());
+    }
+
+    #[test]
+    fn max_fill_within_limits() {
+        let orderbook = book();
+
+        // 100 for 1 and 101 for 1 average exactly 50 bps over the best ask
+        let fill = orderbook.max_fill(Side::Buy, &FillLimit::SlippageBps(decimal("50"))).unwrap();
+        assert_eq!(fill.quantity, decimal("2"));
+        assert_eq!(fill.notional, decimal("201"));
+
+        // Part of the 104 level: (201 + 104x) / (2 + x) = 101
+        let fill = orderbook.max_fill(Side::Buy, &FillLimit::SlippageBps(decimal("100"))).unwrap();
+        assert_eq!(fill.quantity, decimal("0.3333333333") + decimal("2"));
+        assert_eq!(fill.worst_price, decimal("104"));
+        assert!(fill.slippage_bps <= decimal("100"));
+
+        let fill = orderbook.max_fill(Side::Sell, &FillLimit::WorstPrice(decimal("98"))).unwrap();
+        assert_eq!(fill.quantity, decimal("3"));
+        assert_eq!(fill.notional, decimal("295"));
+        let fill = orderbook.max_fill(Side::Sell, &FillLimit::WorstPrice(decimal("98.5"))).unwrap();
+        assert_eq!(fill.quantity, decimal("1"));
+
+        let fill = orderbook.max_fill(Side::Buy, &FillLimit::SlippageBps(decimal("0"))).unwrap();
+        assert_eq!(fill.quantity, decimal("1"));
+        let fill = orderbook.max_fill(Side::Buy, &FillLimit::WorstPrice(decimal("99"))).unwrap();
+        assert_eq!(fill.quantity, decimal("0"));
+        assert_eq!(fill.notional, decimal("0"));
+        assert!(orderbook.max_fill(Side::Buy, &FillLimit::SlippageBps(decimal("-1"))).is_err());
+    }
+
+    #[test]
+    fn fills_by_notional() {
+        let orderbook = book();
+        // 100 from the first ask, 101 from the second and 50 more at 104
+        let fill = orderbook.execution_for_notional(Side::Buy, &decimal("251")).unwrap();
+        assert_eq!(fill.filled, decimal("2.4807692307"));
+        assert_eq!(fill.notional, decimal("251"));
+        assert_eq!(fill.worst_price, decimal("104"));
+
+        let fill = orderbook.execution_for_notional(Side::Sell, &decimal("1000")).unwrap();
+        assert_eq!(fill.filled, decimal("3"));
+        assert_eq!(fill.notional, decimal("295"));
+        assert!(orderbook.execution_for_notional(Side::Sell, &decimal("0")).is_err());
+    }
+}
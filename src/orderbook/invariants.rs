@@ -0,0 +1,136 @@
+use bigdecimal::{BigDecimal, Zero};
+use serde::Deserialize;
+
+use super::{BookSide, OrderBook, OrderBookDepth};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvariantReaction {
+    /// Count the violation in the metrics.
+    Metric,
+    /// Count it and log the broken book.
+    Log,
+    /// Count it, log it, and rebuild the book from a fresh snapshot.
+    Resync,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvariantConfig {
+    /// Checks one in this many applied diffs per book, 0 never does.
+    pub sample_every: u64,
+    /// Checks after every diff regardless of `sample_every`. On by default in debug
+    /// builds, where catching a broken book at once matters more than speed.
+    pub check_every_diff: bool,
+    pub reaction: InvariantReaction,
+}
+
+impl Default for InvariantConfig {
+    fn default() -> InvariantConfig {
+        InvariantConfig {
+            sample_every: 100,
+            check_every_diff: cfg!(debug_assertions),
+            reaction: InvariantReaction::Log,
+        }
+    }
+}
+
+impl InvariantConfig {
+    /// Whether the diff that brought a book to `applied` diffs should be followed by a check.
+    pub fn is_due(&self, applied: u64) -> bool {
+        self.check_every_diff || (self.sample_every > 0 && applied.is_multiple_of(self.sample_every))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvariantViolation {
+    /// The best bid is at or above the best ask.
+    Crossed { bid: BigDecimal, ask: BigDecimal },
+    /// A level with no quantity, which should have been removed.
+    EmptyLevel { side: BookSide, price: BigDecimal },
+    /// A level that is not strictly worse than the one before it.
+    OutOfOrder { side: BookSide, price: BigDecimal },
+}
+
+impl InvariantViolation {
+    /// Metric label of the broken invariant.
+    pub fn name(&self) -> &'static str {
+        match self {
+            InvariantViolation::Crossed { .. } => "crossed",
+            InvariantViolation::EmptyLevel { .. } => "empty_level",
+            InvariantViolation::OutOfOrder { .. } => "out_of_order",
+        }
+    }
+}
+
+fn side_violations(side: BookSide, levels: &OrderBookDepth, violations: &mut Vec<InvariantViolation>) {
+    for (i, (price, quantity)) in levels.iter().enumerate() {
+        if *quantity <= BigDecimal::zero() {
+            violations.push(InvariantViolation::EmptyLevel { side, price: price.clone() });
+        }
+        let Some((previous, _)) = i.checked_sub(1).and_then(|previous| levels.get(previous)) else {
+            continue;
+        };
+        let in_order = match side {
+            BookSide::Bids => price < previous,
+            BookSide::Asks => price > previous,
+        };
+        if !in_order {
+            violations.push(InvariantViolation::OutOfOrder { side, price: price.clone() });
+        }
+    }
+}
+
+impl OrderBook {
+    /// Every invariant the book breaks, empty for a sound book.
+    pub fn invariant_violations(&self) -> Vec<InvariantViolation> {
+        let mut violations = Vec::new();
+        if let (Some((bid, _)), Some((ask, _))) = (self.bids.first(), self.asks.first()) {
+            if bid >= ask {
+                violations.push(InvariantViolation::Crossed { bid: bid.clone(), ask: ask.clone() });
+            }
+        }
+        side_violations(BookSide::Bids, &self.bids, &mut violations);
+        side_violations(BookSide::Asks, &self.asks, &mut violations);
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::{InvariantConfig, InvariantViolation};
+    use crate::orderbook::{BookSide, OrderBook, Pair};
+
+    fn level(price: u32, quantity: u32) -> (BigDecimal, BigDecimal) {
+        (BigDecimal::from(price), BigDecimal::from(quantity))
+    }
+
+    #[test]
+    fn finds_broken_invariants() {
+        let book = OrderBook::new(Pair::BTCUSDT, vec![level(10, 1), level(9, 2)], vec![level(11, 1), level(12, 2)], 1);
+        assert!(book.invariant_violations().is_empty());
+
+        let book = OrderBook::new(Pair::BTCUSDT, vec![level(11, 1), level(9, 0)], vec![level(11, 1), level(13, 2), level(12, 2)], 1);
+        let violations = book.invariant_violations();
+        assert_eq!(violations, vec![
+            InvariantViolation::Crossed { bid: BigDecimal::from(11), ask: BigDecimal::from(11) },
+            InvariantViolation::EmptyLevel { side: BookSide::Bids, price: BigDecimal::from(9) },
+            InvariantViolation::OutOfOrder { side: BookSide::Asks, price: BigDecimal::from(12) },
+        ]);
+        assert_eq!(violations[0].name(), "crossed");
+    }
+
+    #[test]
+    fn samples_checks_by_applied_diffs() {
+        let config = InvariantConfig { sample_every: 3, check_every_diff: false, ..InvariantConfig::default() };
+        let due = (1..=9).filter(|applied| config.is_due(*applied)).collect::<Vec<u64>>();
+        assert_eq!(due, vec![3, 6, 9]);
+
+        let never = InvariantConfig { sample_every: 0, check_every_diff: false, ..InvariantConfig::default() };
+        assert!((1..=9).all(|applied| !never.is_due(applied)));
+
+        let always = InvariantConfig { sample_every: 0, check_every_diff: true, ..InvariantConfig::default() };
+        assert!((1..=9).all(|applied| always.is_due(applied)));
+    }
+}
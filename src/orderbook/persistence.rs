@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::time::now_micros;
+
+use super::{OrderBook, Pair};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistenceConfig {
+    pub dir: PathBuf,
+    /// How often live books are written out.
+    pub interval: Duration,
+    /// Books saved longer ago than this are not used for a warm start.
+    pub max_age: Duration,
+    /// Save every live book that changed once more when the manager shuts down.
+    pub save_on_shutdown: bool,
+}
+
+/// On disk form of a book, the book's own fields plus the time it was saved.
+#[derive(Serialize, Deserialize)]
+struct PersistedBook<B> {
+    /// Microseconds since the Unix epoch.
+    saved_at: u64,
+    #[serde(flatten)]
+    orderbook: B,
+}
+
+fn book_path(dir: &Path, pair: Pair) -> PathBuf {
+    dir.join(format!("{:?}.json", pair))
+}
+
+/// Writes `orderbook` to `<dir>/<SYMBOL>.json`. The file is written next to the
+/// target and renamed over it, so a crash never leaves a truncated book behind.
+pub fn save_orderbook(dir: &Path, orderbook: &OrderBook) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+
+    let persisted = PersistedBook {
+        saved_at: now_micros(),
+        orderbook,
+    };
+    let path = book_path(dir, orderbook.symbol);
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_vec(&persisted)?)?;
+    fs::rename(&tmp_path, &path)
+}
+
+/// Reads the book saved for `pair`, if there is one no older than `max_age`.
+pub fn load_orderbook(dir: &Path, pair: Pair, max_age: Duration) -> Result<Option<OrderBook>, Error> {
+    let path = book_path(dir, pair);
+    let contents = match fs::read(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let persisted: PersistedBook<OrderBook> = serde_json::from_slice(&contents)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Invalid saved book {}: {}", path.display(), err)))?;
+    if persisted.orderbook.symbol != pair {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Saved book {} holds {:?} instead of {:?}", path.display(), persisted.orderbook.symbol, pair),
+        ));
+    }
+    if Duration::from_micros(now_micros().saturating_sub(persisted.saved_at)) > max_age {
+        return Ok(None);
+    }
+
+    Ok(Some(persisted.orderbook))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, str::FromStr, time::Duration};
+
+    use bigdecimal::BigDecimal;
+
+    use super::{load_orderbook, save_orderbook};
+    use crate::orderbook::{OrderBook, Pair};
+
+    #[test]
+    fn saves_and_loads_books() {
+        let dir = std::env::temp_dir().join(format!("challenge-persistence-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let level = |price: &str, quantity: &str| (BigDecimal::from_str(price).unwrap(), BigDecimal::from_str(quantity).unwrap());
+        let orderbook = OrderBook::new(
+            Pair::BTCUSDT,
+            vec![level("100.10", "1.5"), level("100.00", "0.00000001")],
+            vec![level("100.20", "2")],
+            42,
+        );
+        save_orderbook(&dir, &orderbook).unwrap();
+        assert!(dir.join("BTCUSDT.json").exists());
+
+        let loaded = load_orderbook(&dir, Pair::BTCUSDT, Duration::from_secs(60)).unwrap().unwrap();
+        assert_eq!(loaded.last_update_id, 42);
+        assert_eq!(loaded.bids, orderbook.bids);
+        assert_eq!(loaded.asks, orderbook.asks);
+
+        assert!(load_orderbook(&dir, Pair::ETHUSDT, Duration::from_secs(60)).unwrap().is_none());
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(load_orderbook(&dir, Pair::BTCUSDT, Duration::from_millis(1)).unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
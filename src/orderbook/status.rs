@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::Pair;
+
+/// A live book that has not seen a diff for this long is reported as stale.
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookState {
+    /// Waiting for the first snapshot since startup.
+    Bootstrapping,
+    Live,
+    /// Was live, waiting for a fresh snapshot after a gap or a reconnect.
+    Resyncing,
+    /// Live, but no diff has been applied for longer than the stale threshold.
+    Stale,
+}
+
+/// How a tracked book is doing, as reported by the manager.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookStatus {
+    pub symbol: Pair,
+    pub state: BookState,
+    /// `None` while there is no book.
+    pub last_update_id: Option<i64>,
+    /// Time since a diff was last applied, `None` if none has been yet.
+    pub last_diff_age: Option<Duration>,
+    /// Time since the exchange emitted the latest applied diff that carried an event
+    /// time. Includes the clock offset between the exchange and this host.
+    pub last_event_age: Option<Duration>,
+}
+
+/// What the manager remembers about a pair to report its [`BookStatus`].
+#[derive(Debug, Clone, Default)]
+pub struct BookTracking {
+    /// When the book last went live, in microseconds. Kept through resyncs.
+    pub live_at: Option<u64>,
+    pub last_diff_at: Option<u64>,
+    /// Exchange event time of the latest applied diff that had one.
+    pub last_event_at: Option<u64>,
+}
+
+impl BookTracking {
+    pub fn diff_applied(&mut self, at: u64, event_time: Option<u64>) {
+        self.last_diff_at = Some(at);
+        if event_time.is_some() {
+            self.last_event_at = event_time;
+        }
+    }
+
+    /// Status at `now` (microseconds), given the book's `last_update_id` if it has one.
+    /// Without a `stale_after` threshold a live book is never stale.
+    pub fn status(&self, symbol: Pair, last_update_id: Option<i64>, stale_after: Option<Duration>, now: u64) -> BookStatus {
+        let age = |at: u64| Duration::from_micros(now.saturating_sub(at));
+        let state = match (last_update_id, self.live_at) {
+            (None, None) => BookState::Bootstrapping,
+            (None, Some(_)) => BookState::Resyncing,
+            (Some(_), live_at) => {
+                // A book that just went live is fresh even before its first diff
+                let fresh_since = self.last_diff_at.max(live_at);
+                let fresh = match stale_after {
+                    Some(stale_after) => fresh_since.is_some_and(|at| age(at) <= stale_after),
+                    None => true,
+                };
+                if fresh {
+                    BookState::Live
+                } else {
+                    BookState::Stale
+                }
+            },
+        };
+
+        BookStatus {
+            symbol,
+            state,
+            last_update_id,
+            last_diff_age: self.last_diff_at.map(age),
+            last_event_age: self.last_event_at.map(age),
+        }
+    }
+}
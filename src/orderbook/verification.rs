@@ -0,0 +1,143 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bigdecimal::BigDecimal;
+
+use super::{OrderBook, OrderBookDepth, OrderBookDiff};
+
+/// Applied diffs kept per pair to bring a verification snapshot up to the live book.
+pub const VERIFICATION_DIFF_BUFFER: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationConfig {
+    /// Time between checks of each book against a REST snapshot.
+    pub interval: Duration,
+    /// Drop a book that does not match its snapshot and rebuild it.
+    pub resync_on_mismatch: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookSide {
+    Bids,
+    Asks,
+}
+
+/// A price level where the live book and the reference disagree. A missing
+/// quantity means the level is absent on that side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelMismatch {
+    pub side: BookSide,
+    pub price: BigDecimal,
+    pub local: Option<BigDecimal>,
+    pub reference: Option<BigDecimal>,
+}
+
+/// Result of checking a live book against a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verification {
+    Match,
+    Mismatch(Vec<LevelMismatch>),
+    /// The buffered diffs do not reach back to the snapshot, so the two cannot be
+    /// lined up at the same update id.
+    Inconclusive,
+}
+
+/// Brings `reference` up to `target` by applying the diffs in `diffs` past it, in
+/// order. `None` when they leave a gap or do not end at `target`.
+pub fn align(mut reference: OrderBook, diffs: &VecDeque<OrderBookDiff>, target: i64) -> Option<OrderBook> {
+    for diff in diffs {
+        if diff.last_update_id <= reference.last_update_id {
+            continue;
+        }
+        if diff.first_update_id > reference.last_update_id + 1 {
+            return None;
+        }
+        reference.handle_diff(diff.clone()).ok()?;
+    }
+    (reference.last_update_id == target).then_some(reference)
+}
+
+/// Levels that differ between `local` and `reference` at the same update id. Only
+/// prices within the range of `bounds`, the snapshot as fetched, are compared since
+/// both books may hold levels past what the snapshot went down to.
+pub fn compare(local: &OrderBook, reference: &OrderBook, bounds: &OrderBook) -> Vec<LevelMismatch> {
+    let bid_floor = bounds.bids.last().map(|(price, _)| price);
+    let ask_ceiling = bounds.asks.last().map(|(price, _)| price);
+
+    let mut mismatches = compare_side(BookSide::Bids, &local.bids, &reference.bids, |price| bid_floor.is_none_or(|floor| price >= floor));
+    mismatches.extend(compare_side(BookSide::Asks, &local.asks, &reference.asks, |price| ask_ceiling.is_none_or(|ceiling| price <= ceiling)));
+    mismatches
+}
+
+fn compare_side(side: BookSide, local: &OrderBookDepth, reference: &OrderBookDepth, in_range: impl Fn(&BigDecimal) -> bool) -> Vec<LevelMismatch> {
+    let quantity_at = |levels: &OrderBookDepth, price: &BigDecimal| levels.iter().find(|(p, _)| p == price).map(|(_, quantity)| quantity.clone());
+
+    let mut mismatches = Vec::new();
+    for (price, quantity) in local.iter().filter(|(price, _)| in_range(price)) {
+        let expected = quantity_at(reference, price);
+        if expected.as_ref() != Some(quantity) {
+            mismatches.push(LevelMismatch {
+                side,
+                price: price.clone(),
+                local: Some(quantity.clone()),
+                reference: expected,
+            });
+        }
+    }
+    for (price, quantity) in reference.iter().filter(|(price, _)| in_range(price)) {
+        if quantity_at(local, price).is_none() {
+            mismatches.push(LevelMismatch {
+                side,
+                price: price.clone(),
+                local: None,
+                reference: Some(quantity.clone()),
+            });
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use bigdecimal::BigDecimal;
+
+    use super::{align, compare, BookSide};
+    use crate::orderbook::{OrderBook, OrderBookDiff, Pair};
+
+    fn level(price: u32, quantity: u32) -> (BigDecimal, BigDecimal) {
+        (BigDecimal::from(price), BigDecimal::from(quantity))
+    }
+
+    #[test]
+    fn aligns_and_compares_snapshots() {
+        let snapshot = OrderBook::new(Pair::BTCUSDT, vec![level(10, 1), level(9, 2)], vec![level(11, 1), level(12, 2)], 5);
+        let diffs: VecDeque<OrderBookDiff> = vec![
+            OrderBookDiff { bids: vec![level(10, 3)], asks: vec![], first_update_id: 3, last_update_id: 4, event_time: None },
+            OrderBookDiff { bids: vec![level(9, 0)], asks: vec![level(11, 4)], first_update_id: 5, last_update_id: 7, event_time: None },
+            OrderBookDiff { bids: vec![], asks: vec![level(12, 5)], first_update_id: 8, last_update_id: 9, event_time: None },
+        ]
+        .into();
+
+        let aligned = align(snapshot.clone(), &diffs, 9).unwrap();
+        assert_eq!(aligned.bids, vec![level(10, 1)]);
+        assert_eq!(aligned.asks, vec![level(11, 4), level(12, 5)]);
+        assert!(align(snapshot.clone(), &diffs, 7).is_none());
+        assert!(align(snapshot.clone(), &diffs.iter().skip(2).cloned().collect(), 9).is_none());
+
+        // Levels past the snapshot's deepest prices are not compared
+        let mut local = aligned.clone();
+        local.bids.push(level(5, 1));
+        local.asks.push(level(20, 1));
+        assert!(compare(&local, &aligned, &snapshot).is_empty());
+
+        local.bids[0] = level(10, 2);
+        local.asks.remove(1);
+        let mismatches = compare(&local, &aligned, &snapshot);
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].side, BookSide::Bids);
+        assert_eq!(mismatches[0].reference, Some(BigDecimal::from(1)));
+        assert_eq!(mismatches[1].side, BookSide::Asks);
+        assert_eq!(mismatches[1].local, None);
+    }
+}
@@ -1,9 +1,22 @@
-use std::str::FromStr;
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
 
-use actix_web::{error, get, web, Responder, Result};
+use actix_web::{error, get, post, web, CustomizeResponder, Responder, Result};
 use bigdecimal::BigDecimal;
 use serde::{Deserialize, Deserializer, Serialize};
-use crate::{orderbook::Pair, AppState};
+use challenge::{
+    binance,
+    candles::{Candle, Ohlc},
+    convert::{self, haircut_bps, MAX_ROUTE_LEGS},
+    history::{self, TopOfBook},
+    orderbook::{BookState, DepthPoint, Fill, FillLimit, OrderBook, OrderBookDepth, Pair, Side},
+    synthetic::SyntheticPair,
+    time::now_micros,
+};
+
+use crate::AppState;
 
 #[derive(Serialize)]
 struct TipsResponse {
@@ -11,36 +24,100 @@ struct TipsResponse {
     ask: [String; 2],
 }
 
-#[get("/price-tips/{pair}")]
-async fn get_price_tips(path: web::Path<String>, data: web::Data<AppState>) -> Result<impl Responder> {
-    let pair = match path.into_inner() {
-        pair if pair == "BTCUSDT" => Pair::BTCUSDT,
-        pair if pair == "ETHUSDT" => Pair::ETHUSDT,
-        _ => return Err(error::ErrorBadRequest("Invalid pair")),
-    };
+fn parse_pair(pair: &str) -> Result<Pair> {
+    match pair {
+        "BTCUSDT" => Ok(Pair::BTCUSDT),
+        "ETHUSDT" => Ok(Pair::ETHUSDT),
+        _ => Err(error::ErrorBadRequest("Invalid pair")),
+    }
+}
 
-    let (bid, ask) = data.binance_client.get_tips(pair).await.unwrap();
+/// Formats without an exponent. Products come out with trailing zeros stripped, which
+/// `Display` would otherwise print as e.g. `2E+2`.
+fn plain(value: &BigDecimal) -> String {
+    let (_, scale) = value.as_bigint_and_exponent();
+    if scale < 0 {
+        value.with_scale(0).to_string()
+    } else {
+        value.to_string()
+    }
+}
 
-    Ok(web::Json(TipsResponse {
-        bid: [bid.0.to_string(), bid.1.to_string()],
-        ask: [ask.0.to_string(), ask.1.to_string()],
-    }))
+/// Header naming the stale books a response was computed on, see [`StalePolicy::Flag`].
+const STALE_HEADER: &str = "x-stale-books";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StalePolicy {
+    /// Answer 503 when a book the response needs is stale.
+    #[default]
+    Reject,
+    /// Answer anyway, naming the stale books in [`STALE_HEADER`].
+    Flag,
 }
 
-impl<'de> Deserialize<'de> for Pair {
-    fn deserialize<D>(deserializer: D) -> Result<Pair, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        match s.as_str() {
-            "BTCUSDT" => Ok(Pair::BTCUSDT),
-            "ETHUSDT" => Ok(Pair::ETHUSDT),
-            _ => Err(serde::de::Error::custom("invalid pair")),
-        }
+#[derive(Deserialize)]
+struct StaleParams {
+    #[serde(default)]
+    stale: StalePolicy,
+}
+
+/// The stale books among `pairs`, or an error if there are any and the request
+/// does not accept them.
+async fn stale_books(pairs: &[Pair], params: &StaleParams, data: &AppState) -> Result<Vec<Pair>> {
+    let stale = data
+        .binance_client
+        .get_status()
+        .await
+        .map_err(error::ErrorServiceUnavailable)?
+        .into_iter()
+        .filter(|status| status.state == BookState::Stale && pairs.contains(&status.symbol))
+        .map(|status| status.symbol)
+        .collect::<Vec<Pair>>();
+
+    if !stale.is_empty() && params.stale == StalePolicy::Reject {
+        return Err(error::ErrorServiceUnavailable(format!("Orderbook for {} is stale, pass stale=flag to use it anyway", stale_symbols(&stale))));
+    }
+    Ok(stale)
+}
+
+fn stale_symbols(stale: &[Pair]) -> String {
+    stale.iter().map(|pair| format!("{:?}", pair)).collect::<Vec<String>>().join(",")
+}
+
+fn flag_stale<R: Responder>(responder: R, stale: &[Pair]) -> CustomizeResponder<R> {
+    let responder = responder.customize();
+    if stale.is_empty() {
+        return responder;
+    }
+    responder.insert_header((STALE_HEADER, stale_symbols(stale)))
+}
+
+/// Books a tracked or synthetic symbol is priced from.
+fn pairs_for_symbol(symbol: &str, data: &AppState) -> Result<Vec<Pair>> {
+    match data.synthetic_pair(symbol) {
+        Some(synthetic) => Ok(synthetic.pairs()),
+        None => Ok(vec![parse_pair(symbol)?]),
     }
 }
 
+#[get("/price-tips/{pair}")]
+async fn get_price_tips(path: web::Path<String>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let stale = stale_books(&pairs_for_symbol(&path, &data)?, &stale, &data).await?;
+    let (bid, ask) = match data.synthetic_pair(&path) {
+        Some(synthetic) => {
+            let books = data.binance_client.get_orderbooks(&synthetic.pairs()).await.map_err(error::ErrorServiceUnavailable)?;
+            synthetic.tips(&books).map_err(error::ErrorServiceUnavailable)?
+        },
+        None => data.binance_client.get_tips(parse_pair(&path)?).await.map_err(error::ErrorServiceUnavailable)?,
+    };
+
+    Ok(flag_stale(web::Json(TipsResponse {
+        bid: [bid.0.to_string(), bid.1.to_string()],
+        ask: [ask.0.to_string(), ask.1.to_string()],
+    }), &stale))
+}
+
 enum Operation {
   Buy,
   Sell,
@@ -62,36 +139,1398 @@ impl<'de> Deserialize<'de> for Operation {
 
 #[derive(Deserialize)]
 struct ExecutionParams {
-    pair: Pair,
+    pair: String,
     operation: Operation,
     amount: String,
 }
 
+/// Average price of a synthetic pair, walking the depth of each of its legs.
+async fn synthetic_execution_price(synthetic: &SyntheticPair, side: Side, amount: &BigDecimal, data: &AppState) -> Result<String> {
+    let books = data.binance_client.get_orderbooks(&synthetic.pairs()).await.map_err(error::ErrorServiceUnavailable)?;
+    let fill = synthetic.execution(side, amount, &books).map_err(|err| match err.kind() {
+        std::io::ErrorKind::InvalidInput => error::ErrorBadRequest(err),
+        _ => error::ErrorServiceUnavailable(err),
+    })?;
+
+    Ok(format!("Average Price: {}", plain(&fill.average_price)))
+}
+
 #[get("/execution-price")]
-async fn get_execution_price(info: web::Query<ExecutionParams>, data: web::Data<AppState>) -> Result<String> {
-    let depth = match info.operation {
-        Operation::Buy => data.binance_client.get_asks(info.pair).await.unwrap(),
-        Operation::Sell => data.binance_client.get_bids(info.pair).await.unwrap(),
+async fn get_execution_price(info: web::Query<ExecutionParams>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let amount = BigDecimal::from_str(&info.amount).map_err(|_| error::ErrorBadRequest("Invalid amount"))?;
+    if amount <= BigDecimal::from(0) {
+        return Err(error::ErrorBadRequest("amount must be positive"));
+    }
+    let side = match info.operation {
+        Operation::Buy => Side::Buy,
+        Operation::Sell => Side::Sell,
+    };
+
+    let stale = stale_books(&pairs_for_symbol(&info.pair, &data)?, &stale, &data).await?;
+    if let Some(synthetic) = data.synthetic_pair(&info.pair) {
+        return Ok(flag_stale(synthetic_execution_price(synthetic, side, &amount, &data).await?, &stale));
+    }
+
+    let pair = parse_pair(&info.pair)?;
+    let orderbook = data.binance_client.get_orderbook(pair).await.map_err(error::ErrorServiceUnavailable)?;
+    let fill = orderbook.execution(side, &amount).map_err(|err| match err.kind() {
+        std::io::ErrorKind::InvalidInput => error::ErrorBadRequest(err),
+        _ => error::ErrorServiceUnavailable(err),
+    })?;
+    if !fill.is_complete() {
+        return Err(error::ErrorServiceUnavailable(format!("Not enough depth on {:?}", pair)));
+    }
+
+    Ok(flag_stale(format!("Average Price: {}", plain(&fill.average_price)), &stale))
+}
+
+/// Most points a history request may return.
+const MAX_HISTORY_POINTS: u64 = 10_000;
+const DEFAULT_HISTORY_WINDOW_MS: u64 = 60 * 60 * 1000;
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    /// Milliseconds since the Unix epoch, defaults to an hour before `to`.
+    from: Option<u64>,
+    /// Milliseconds since the Unix epoch, defaults to now.
+    to: Option<u64>,
+    /// Resampling step such as `1s` or `5m`, every recorded change is returned without it.
+    interval: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HistoryPoint {
+    /// Milliseconds since the Unix epoch.
+    time: u64,
+    bid: String,
+    bid_quantity: String,
+    ask: String,
+    ask_quantity: String,
+    mid: String,
+    spread: String,
+}
+
+impl From<&TopOfBook> for HistoryPoint {
+    fn from(entry: &TopOfBook) -> HistoryPoint {
+        HistoryPoint {
+            time: entry.time / 1000,
+            bid: plain(&entry.bid),
+            bid_quantity: plain(&entry.bid_quantity),
+            ask: plain(&entry.ask),
+            ask_quantity: plain(&entry.ask_quantity),
+            mid: plain(&entry.mid),
+            spread: plain(&entry.spread),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HistoryResponse {
+    pair: Pair,
+    from: u64,
+    to: u64,
+    interval_ms: Option<u64>,
+    points: Vec<HistoryPoint>,
+}
+
+#[get("/history/{pair}")]
+async fn get_history(path: web::Path<String>, params: web::Query<HistoryParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let pair = parse_pair(&path)?;
+    let to = params.to.unwrap_or_else(|| now_micros() / 1000);
+    let from = params.from.unwrap_or_else(|| to.saturating_sub(DEFAULT_HISTORY_WINDOW_MS));
+    if from > to {
+        return Err(error::ErrorBadRequest("from must not be after to"));
+    }
+    let interval = params
+        .interval
+        .as_deref()
+        .map(history::parse_interval)
+        .transpose()
+        .map_err(error::ErrorBadRequest)?;
+    if let Some(interval) = interval {
+        if (to - from) / (interval.as_millis() as u64).max(1) >= MAX_HISTORY_POINTS {
+            return Err(error::ErrorBadRequest("Too many points, use a larger interval or a shorter range"));
+        }
+    }
+
+    let (from_micros, to_micros) = (from.saturating_mul(1000), to.saturating_mul(1000).saturating_add(999));
+    let entries = data.binance_client.get_history(pair, from_micros, to_micros).await.map_err(error::ErrorServiceUnavailable)?;
+    let points = match interval {
+        Some(interval) => history::resample(&entries, from_micros, to_micros, interval).iter().map(HistoryPoint::from).collect(),
+        None => {
+            let points = entries.iter().filter(|entry| entry.time >= from_micros).map(HistoryPoint::from).collect::<Vec<HistoryPoint>>();
+            if points.len() as u64 > MAX_HISTORY_POINTS {
+                return Err(error::ErrorBadRequest("Too many points, set an interval or use a shorter range"));
+            }
+            points
+        },
+    };
+
+    Ok(web::Json(HistoryResponse {
+        pair,
+        from,
+        to,
+        interval_ms: interval.map(|interval| interval.as_millis() as u64),
+        points,
+    }))
+}
+
+const DEFAULT_CANDLE_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+struct CandleParams {
+    /// One of the configured intervals, defaults to `1m`.
+    interval: Option<String>,
+    /// Most recent bars returned, defaults to 100.
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct OhlcResponse {
+    open: String,
+    high: String,
+    low: String,
+    close: String,
+}
+
+impl From<&Ohlc> for OhlcResponse {
+    fn from(ohlc: &Ohlc) -> OhlcResponse {
+        OhlcResponse {
+            open: plain(&ohlc.open),
+            high: plain(&ohlc.high),
+            low: plain(&ohlc.low),
+            close: plain(&ohlc.close),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CandleResponse {
+    /// Milliseconds since the Unix epoch.
+    start: u64,
+    mid: OhlcResponse,
+    microprice: OhlcResponse,
+    updates: u64,
+}
+
+impl From<&Candle> for CandleResponse {
+    fn from(candle: &Candle) -> CandleResponse {
+        CandleResponse {
+            start: candle.start / 1000,
+            mid: OhlcResponse::from(&candle.mid),
+            microprice: OhlcResponse::from(&candle.microprice),
+            updates: candle.updates,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CandlesResponse {
+    pair: Pair,
+    interval_ms: u64,
+    candles: Vec<CandleResponse>,
+}
+
+#[get("/candles/{pair}")]
+async fn get_candles(path: web::Path<String>, params: web::Query<CandleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let pair = parse_pair(&path)?;
+    let interval = history::parse_interval(params.interval.as_deref().unwrap_or("1m")).map_err(error::ErrorBadRequest)?;
+    let limit = params.limit.unwrap_or(DEFAULT_CANDLE_LIMIT);
+
+    let candles = data.binance_client.get_candles(pair, interval, limit).await.map_err(|err| match err.kind() {
+        std::io::ErrorKind::InvalidInput => error::ErrorBadRequest(err),
+        _ => error::ErrorServiceUnavailable(err),
+    })?;
+
+    Ok(web::Json(CandlesResponse {
+        pair,
+        interval_ms: interval.as_millis() as u64,
+        candles: candles.iter().map(CandleResponse::from).collect(),
+    }))
+}
+
+const DEFAULT_IMBALANCE_LEVELS: usize = 5;
+const DEFAULT_DEPTH_BANDS: &str = "10,50,100";
+const MAX_DEPTH_BANDS: usize = 20;
+
+#[derive(Deserialize)]
+struct AnalyticsParams {
+    /// Levels per side used for the top of book imbalance, defaults to 5.
+    levels: Option<usize>,
+    /// Comma separated bands around mid in basis points, defaults to `10,50,100`.
+    bps: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BandResponse {
+    bps: String,
+    bid_quantity: String,
+    bid_notional: String,
+    ask_quantity: String,
+    ask_notional: String,
+    imbalance: String,
+}
+
+#[derive(Serialize)]
+struct AnalyticsResponse {
+    pair: Pair,
+    last_update_id: i64,
+    bid: String,
+    ask: String,
+    mid: String,
+    spread: String,
+    spread_bps: String,
+    microprice: String,
+    imbalance_levels: usize,
+    imbalance: String,
+    depth: Vec<BandResponse>,
+}
+
+fn parse_bands(bands: &str) -> Result<Vec<BigDecimal>> {
+    let bands = bands
+        .split(',')
+        .map(|band| BigDecimal::from_str(band.trim()).ok().filter(|band| *band > BigDecimal::from(0)))
+        .collect::<Option<Vec<BigDecimal>>>()
+        .ok_or_else(|| error::ErrorBadRequest("Invalid bps, expected positive numbers separated by commas"))?;
+    if bands.len() > MAX_DEPTH_BANDS {
+        return Err(error::ErrorBadRequest(format!("At most {} bps bands are allowed", MAX_DEPTH_BANDS)));
+    }
+    Ok(bands)
+}
+
+#[get("/analytics/{pair}")]
+async fn get_analytics(path: web::Path<String>, params: web::Query<AnalyticsParams>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let pair = parse_pair(&path)?;
+    let levels = params.levels.unwrap_or(DEFAULT_IMBALANCE_LEVELS);
+    let bands = parse_bands(params.bps.as_deref().unwrap_or(DEFAULT_DEPTH_BANDS))?;
+
+    let stale = stale_books(&[pair], &stale, &data).await?;
+    let orderbook = data.binance_client.get_orderbook(pair).await.map_err(error::ErrorServiceUnavailable)?;
+    let ((bid, _), (ask, _)) = orderbook.get_tips().map_err(error::ErrorServiceUnavailable)?;
+    let depth = bands
+        .iter()
+        .map(|bps| {
+            orderbook.depth_within_bps(bps).map(|depth| BandResponse {
+                bps: plain(bps),
+                imbalance: plain(&depth.imbalance()),
+                bid_quantity: plain(&depth.bid_quantity),
+                bid_notional: plain(&depth.bid_notional),
+                ask_quantity: plain(&depth.ask_quantity),
+                ask_notional: plain(&depth.ask_notional),
+            })
+        })
+        .collect::<Result<Vec<BandResponse>, std::io::Error>>()
+        .map_err(error::ErrorServiceUnavailable)?;
+
+    Ok(flag_stale(web::Json(AnalyticsResponse {
+        pair,
+        last_update_id: orderbook.last_update_id(),
+        bid: plain(&bid),
+        ask: plain(&ask),
+        mid: plain(&orderbook.mid().map_err(error::ErrorServiceUnavailable)?),
+        spread: plain(&orderbook.spread().map_err(error::ErrorServiceUnavailable)?),
+        spread_bps: plain(&orderbook.spread_bps().map_err(error::ErrorServiceUnavailable)?),
+        microprice: plain(&orderbook.microprice().map_err(error::ErrorServiceUnavailable)?),
+        imbalance_levels: levels,
+        imbalance: plain(&orderbook.imbalance(levels)),
+        depth,
+    }), &stale))
+}
+
+/// Most sizes an impact curve request may ask for.
+const MAX_IMPACT_SIZES: usize = 100;
+
+#[derive(Deserialize)]
+struct ImpactParams {
+    side: Side,
+    /// Comma separated base quantities.
+    sizes: Option<String>,
+    /// First size of a geometric ladder, used with `factor` and `steps` instead of `sizes`.
+    start: Option<String>,
+    factor: Option<String>,
+    steps: Option<usize>,
+}
+
+impl ImpactParams {
+    fn sizes(&self) -> Result<Vec<BigDecimal>> {
+        let invalid_number = |_| error::ErrorBadRequest("Invalid number");
+        let sizes = match (&self.sizes, &self.start, &self.factor, self.steps) {
+            (Some(sizes), None, None, None) => sizes
+                .split(',')
+                .map(|size| BigDecimal::from_str(size.trim()).map_err(invalid_number))
+                .collect::<Result<Vec<BigDecimal>>>()?,
+            (None, Some(start), Some(factor), Some(steps)) => {
+                let factor = BigDecimal::from_str(factor).map_err(invalid_number)?;
+                if factor <= BigDecimal::from(1) {
+                    return Err(error::ErrorBadRequest("factor must be greater than 1"));
+                }
+                if steps > MAX_IMPACT_SIZES {
+                    return Err(error::ErrorBadRequest(format!("At most {} sizes are allowed", MAX_IMPACT_SIZES)));
+                }
+                let mut size = BigDecimal::from_str(start).map_err(invalid_number)?;
+                let mut sizes = Vec::with_capacity(steps);
+                for _ in 0..steps {
+                    sizes.push(size.clone());
+                    size *= &factor;
+                }
+                sizes
+            },
+            _ => return Err(error::ErrorBadRequest("Expected either sizes, or start, factor and steps")),
+        };
+
+        if sizes.is_empty() || sizes.len() > MAX_IMPACT_SIZES {
+            return Err(error::ErrorBadRequest(format!("Between 1 and {} sizes are allowed", MAX_IMPACT_SIZES)));
+        }
+        if sizes.iter().any(|size| *size <= BigDecimal::from(0)) {
+            return Err(error::ErrorBadRequest("Sizes must be positive"));
+        }
+        Ok(sizes)
+    }
+}
+
+#[derive(Serialize)]
+struct FillResponse {
+    size: String,
+    filled: String,
+    complete: bool,
+    notional: String,
+    average_price: String,
+    worst_price: String,
+    slippage_bps: String,
+}
+
+impl From<&Fill> for FillResponse {
+    fn from(fill: &Fill) -> FillResponse {
+        FillResponse {
+            size: plain(&fill.quantity),
+            filled: plain(&fill.filled),
+            complete: fill.is_complete(),
+            notional: plain(&fill.notional),
+            average_price: plain(&fill.average_price),
+            worst_price: plain(&fill.worst_price),
+            slippage_bps: plain(&fill.slippage_bps),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ImpactResponse {
+    pair: Pair,
+    side: Side,
+    last_update_id: i64,
+    best_price: String,
+    mid: String,
+    points: Vec<FillResponse>,
+}
+
+#[get("/impact/{pair}")]
+async fn get_impact_curve(path: web::Path<String>, params: web::Query<ImpactParams>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let pair = parse_pair(&path)?;
+    let sizes = params.sizes()?;
+
+    let stale = stale_books(&[pair], &stale, &data).await?;
+    let orderbook = data.binance_client.get_orderbook(pair).await.map_err(error::ErrorServiceUnavailable)?;
+    let fills = orderbook.impact_curve(params.side, &sizes).map_err(error::ErrorServiceUnavailable)?;
+    let best_price = orderbook.levels_for(params.side).first().map(|(price, _)| plain(price)).unwrap_or_default();
+
+    Ok(flag_stale(web::Json(ImpactResponse {
+        pair,
+        side: params.side,
+        last_update_id: orderbook.last_update_id(),
+        best_price,
+        mid: plain(&orderbook.mid().map_err(error::ErrorServiceUnavailable)?),
+        points: fills.iter().map(FillResponse::from).collect(),
+    }), &stale))
+}
+
+#[derive(Deserialize)]
+struct MaxSizeParams {
+    side: Side,
+    /// Largest distance of the average price from the best price.
+    slippage_bps: Option<String>,
+    /// Worst level price that may be touched, instead of `slippage_bps`.
+    worst_price: Option<String>,
+}
+
+impl MaxSizeParams {
+    fn limit(&self) -> Result<FillLimit> {
+        let parse = |value: &str| BigDecimal::from_str(value).map_err(|_| error::ErrorBadRequest("Invalid number"));
+        match (&self.slippage_bps, &self.worst_price) {
+            (Some(bps), None) => {
+                let bps = parse(bps)?;
+                if bps < BigDecimal::from(0) {
+                    return Err(error::ErrorBadRequest("slippage_bps must not be negative"));
+                }
+                Ok(FillLimit::SlippageBps(bps))
+            },
+            (None, Some(price)) => Ok(FillLimit::WorstPrice(parse(price)?)),
+            _ => Err(error::ErrorBadRequest("Expected either slippage_bps or worst_price")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MaxSizeResponse {
+    pair: Pair,
+    side: Side,
+    last_update_id: i64,
+    best_price: String,
+    /// Base quantity fillable within the limit.
+    quantity: String,
+    /// Quote amount paid or received for `quantity`.
+    notional: String,
+    average_price: String,
+    worst_price: String,
+    slippage_bps: String,
+    /// Whether the whole side fits, so the limit was not what stopped the fill.
+    exhausted: bool,
+}
+
+#[get("/max-size/{pair}")]
+async fn get_max_size(path: web::Path<String>, params: web::Query<MaxSizeParams>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let pair = parse_pair(&path)?;
+    let limit = params.limit()?;
+
+    let stale = stale_books(&[pair], &stale, &data).await?;
+    let orderbook = data.binance_client.get_orderbook(pair).await.map_err(error::ErrorServiceUnavailable)?;
+    let fill = orderbook.max_fill(params.side, &limit).map_err(error::ErrorServiceUnavailable)?;
+    let levels = orderbook.levels_for(params.side);
+    let depth: BigDecimal = levels.iter().map(|(_, quantity)| quantity).sum();
+    let best_price = levels.first().map(|(price, _)| plain(price)).unwrap_or_default();
+
+    Ok(flag_stale(web::Json(MaxSizeResponse {
+        pair,
+        side: params.side,
+        last_update_id: orderbook.last_update_id(),
+        best_price,
+        exhausted: fill.quantity == depth,
+        quantity: plain(&fill.quantity),
+        notional: plain(&fill.notional),
+        average_price: plain(&fill.average_price),
+        worst_price: plain(&fill.worst_price),
+        slippage_bps: plain(&fill.slippage_bps),
+    }), &stale))
+}
+
+const DEFAULT_DEPTH_LEVELS: usize = 20;
+/// Most levels per side a depth request may return.
+const MAX_DEPTH_LEVELS: usize = 1000;
+
+#[derive(Deserialize)]
+struct DepthParams {
+    /// Levels per side, after grouping.
+    limit: Option<usize>,
+    /// Width of the price buckets levels are merged into.
+    group: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DepthResponse {
+    pair: Pair,
+    last_update_id: i64,
+    group: Option<String>,
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+}
+
+fn depth_levels(levels: &OrderBookDepth, limit: usize) -> Vec<[String; 2]> {
+    levels.iter().take(limit).map(|(price, quantity)| [plain(price), plain(quantity)]).collect()
+}
+
+#[get("/depth/{pair}")]
+async fn get_depth(path: web::Path<String>, params: web::Query<DepthParams>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let pair = parse_pair(&path)?;
+    let limit = params.limit.unwrap_or(DEFAULT_DEPTH_LEVELS);
+    if limit == 0 || limit > MAX_DEPTH_LEVELS {
+        return Err(error::ErrorBadRequest(format!("limit must be between 1 and {}", MAX_DEPTH_LEVELS)));
+    }
+    let group = match &params.group {
+        Some(group) => Some(BigDecimal::from_str(group).map_err(|_| error::ErrorBadRequest("Invalid group"))?),
+        None => None,
     };
 
-    let target_amount = BigDecimal::from_str(&info.amount).or_else(|_| Err(error::ErrorBadRequest("Invalid amount")))?;
-    let mut remaining = target_amount.clone();
-    let mut total_cost = BigDecimal::from(0);
-    for (price, amount) in depth.into_iter() {
-        if amount < remaining {
-            total_cost += price * &amount;
-            remaining -= amount;
+    let stale = stale_books(&[pair], &stale, &data).await?;
+    let mut orderbook = data.binance_client.get_orderbook(pair).await.map_err(error::ErrorServiceUnavailable)?;
+    if let Some(tick) = &group {
+        orderbook = orderbook.grouped(tick).map_err(error::ErrorBadRequest)?;
+    }
+
+    Ok(flag_stale(web::Json(DepthResponse {
+        pair,
+        last_update_id: orderbook.last_update_id(),
+        group: group.as_ref().map(plain),
+        bids: depth_levels(orderbook.bids(), limit),
+        asks: depth_levels(orderbook.asks(), limit),
+    }), &stale))
+}
+
+const DEFAULT_CHART_POINTS: usize = 100;
+/// Most points per side a depth chart request may return.
+const MAX_CHART_POINTS: usize = 1000;
+
+#[derive(Deserialize)]
+struct DepthChartParams {
+    /// Points per side.
+    points: Option<usize>,
+    /// Only include levels within this percentage of mid.
+    range_pct: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DepthPointResponse {
+    price: String,
+    quantity: String,
+    notional: String,
+}
+
+impl From<&DepthPoint> for DepthPointResponse {
+    fn from(point: &DepthPoint) -> DepthPointResponse {
+        DepthPointResponse {
+            price: plain(&point.price),
+            quantity: plain(&point.quantity),
+            notional: plain(&point.notional),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DepthChartResponse {
+    pair: Pair,
+    last_update_id: i64,
+    mid: Option<String>,
+    bids: Vec<DepthPointResponse>,
+    asks: Vec<DepthPointResponse>,
+}
+
+#[get("/depth-chart/{pair}")]
+async fn get_depth_chart(path: web::Path<String>, params: web::Query<DepthChartParams>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let pair = parse_pair(&path)?;
+    let points = params.points.unwrap_or(DEFAULT_CHART_POINTS);
+    if points == 0 || points > MAX_CHART_POINTS {
+        return Err(error::ErrorBadRequest(format!("points must be between 1 and {}", MAX_CHART_POINTS)));
+    }
+    let range_pct = match &params.range_pct {
+        Some(range) => {
+            let range = BigDecimal::from_str(range).map_err(|_| error::ErrorBadRequest("Invalid range_pct"))?;
+            if range <= BigDecimal::from(0) {
+                return Err(error::ErrorBadRequest("range_pct must be positive"));
+            }
+            Some(range)
+        },
+        None => None,
+    };
+
+    let stale = stale_books(&[pair], &stale, &data).await?;
+    let orderbook = data.binance_client.get_orderbook(pair).await.map_err(error::ErrorServiceUnavailable)?;
+    let chart = orderbook.depth_chart(points, range_pct.as_ref()).map_err(error::ErrorServiceUnavailable)?;
+
+    Ok(flag_stale(web::Json(DepthChartResponse {
+        pair,
+        last_update_id: orderbook.last_update_id(),
+        mid: orderbook.mid().ok().map(|mid| plain(&mid)),
+        bids: chart.bids.iter().map(DepthPointResponse::from).collect(),
+        asks: chart.asks.iter().map(DepthPointResponse::from).collect(),
+    }), &stale))
+}
+
+#[derive(Deserialize)]
+struct ConvertParams {
+    from: String,
+    to: String,
+    amount: String,
+}
+
+#[derive(Serialize)]
+struct ConversionLegResponse {
+    pair: Pair,
+    /// Side taken on the pair's book.
+    side: Side,
+    /// Asset given up on this leg and how much of it.
+    sell: String,
+    sell_amount: String,
+    /// Asset received on this leg and how much of it.
+    buy: String,
+    buy_amount: String,
+    average_price: String,
+    worst_price: String,
+    slippage_bps: String,
+}
+
+#[derive(Serialize)]
+struct ConversionResponse {
+    from: String,
+    to: String,
+    amount: String,
+    output: String,
+    rate: String,
+    route: Vec<Pair>,
+    legs: Vec<ConversionLegResponse>,
+}
+
+#[get("/convert")]
+async fn get_conversion(params: web::Query<ConvertParams>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    let amount = BigDecimal::from_str(&params.amount).map_err(|_| error::ErrorBadRequest("Invalid amount"))?;
+    if amount <= BigDecimal::from(0) {
+        return Err(error::ErrorBadRequest("amount must be positive"));
+    }
+    if params.from == params.to {
+        return Err(error::ErrorBadRequest("from and to must differ"));
+    }
+    let routes = convert::routes(&data.pairs, &params.from, &params.to, MAX_ROUTE_LEGS);
+    if routes.is_empty() {
+        return Err(error::ErrorBadRequest(format!("No route from {} to {}", params.from, params.to)));
+    }
+
+    let mut pairs: Vec<Pair> = Vec::new();
+    for pair in routes.iter().flat_map(|route| route.pairs()) {
+        if !pairs.contains(&pair) {
+            pairs.push(pair);
+        }
+    }
+    let stale = stale_books(&pairs, &stale, &data).await?;
+    let books = data.binance_client.get_orderbooks(&pairs).await.map_err(error::ErrorServiceUnavailable)?;
+    let conversion = convert::best_conversion(&routes, &books, &amount).map_err(error::ErrorServiceUnavailable)?;
+
+    let mut asset = params.from.clone();
+    let mut legs = Vec::with_capacity(conversion.fills.len());
+    for (leg, fill) in conversion.route.legs().iter().zip(conversion.fills.iter()) {
+        let (base, quote) = binance::assets_for_pair(leg.pair).unwrap_or_default();
+        let (side, buy, sell_amount) = if leg.inverted {
+            (Side::Buy, base, &fill.notional)
         } else {
-            total_cost += price * remaining;
-            break;
+            (Side::Sell, quote, &fill.filled)
+        };
+        legs.push(ConversionLegResponse {
+            pair: leg.pair,
+            side,
+            sell: asset,
+            sell_amount: plain(sell_amount),
+            buy: buy.to_string(),
+            buy_amount: plain(&leg.output(fill)),
+            average_price: plain(&fill.average_price),
+            worst_price: plain(&fill.worst_price),
+            slippage_bps: plain(&fill.slippage_bps),
+        });
+        asset = buy.to_string();
+    }
+
+    Ok(flag_stale(web::Json(ConversionResponse {
+        from: params.from.clone(),
+        to: params.to.clone(),
+        amount: plain(&conversion.amount),
+        output: plain(&conversion.output),
+        rate: plain(&conversion.rate),
+        route: conversion.route.pairs(),
+        legs,
+    }), &stale))
+}
+
+/// Most quotes a batch may ask for.
+const MAX_BATCH_QUOTES: usize = 1000;
+/// Largest batch body accepted, enough for [`MAX_BATCH_QUOTES`] items.
+const MAX_BATCH_BYTES: usize = 256 * 1024;
+
+#[derive(Deserialize)]
+struct QuoteRequest {
+    /// A tracked or synthetic symbol.
+    pair: String,
+    side: Side,
+    /// Base quantity.
+    amount: String,
+}
+
+#[derive(Serialize)]
+struct QuoteResponse {
+    pair: String,
+    side: Side,
+    amount: String,
+    #[serde(flatten)]
+    fill: Option<FillResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct QuotesResponse {
+    /// Update id of every book the quotes were computed on.
+    last_update_ids: HashMap<Pair, i64>,
+    quotes: Vec<QuoteResponse>,
+}
+
+type BatchBooks = HashMap<Pair, std::io::Result<OrderBook>>;
+
+fn batch_book(books: &BatchBooks, pair: Pair) -> std::result::Result<&OrderBook, String> {
+    match books.get(&pair) {
+        Some(Ok(book)) => Ok(book),
+        Some(Err(err)) => Err(err.to_string()),
+        None => Err(format!("Pair {:?} is not tracked", pair)),
+    }
+}
+
+fn batch_quote(request: &QuoteRequest, books: &BatchBooks, data: &AppState) -> std::result::Result<Fill, String> {
+    let amount = BigDecimal::from_str(&request.amount).map_err(|_| "Invalid amount".to_string())?;
+    match data.synthetic_pair(&request.pair) {
+        Some(synthetic) => {
+            let legs = synthetic
+                .pairs()
+                .into_iter()
+                .map(|pair| batch_book(books, pair).cloned())
+                .collect::<std::result::Result<Vec<OrderBook>, String>>()?;
+            synthetic.execution(request.side, &amount, &legs).map_err(|err| err.to_string())
+        },
+        None => {
+            let pair = parse_pair(&request.pair).map_err(|_| "Invalid pair".to_string())?;
+            batch_book(books, pair)?.execution(request.side, &amount).map_err(|err| err.to_string())
+        },
+    }
+}
+
+/// Quotes every item against the same copy of each book. Items that cannot be quoted
+/// carry an error instead of failing the batch.
+#[post("/quotes")]
+async fn post_quotes(requests: web::Json<Vec<QuoteRequest>>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    if requests.len() > MAX_BATCH_QUOTES {
+        return Err(error::ErrorBadRequest(format!("At most {} quotes are allowed", MAX_BATCH_QUOTES)));
+    }
+
+    let mut pairs: Vec<Pair> = Vec::new();
+    for request in requests.iter() {
+        let needed = match data.synthetic_pair(&request.pair) {
+            Some(synthetic) => synthetic.pairs(),
+            None => parse_pair(&request.pair).map(|pair| vec![pair]).unwrap_or_default(),
+        };
+        for pair in needed {
+            if !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
         }
     }
+    let stale = stale_books(&pairs, &stale, &data).await?;
+    let books = data.binance_client.get_each_orderbook(&pairs).await.map_err(error::ErrorServiceUnavailable)?;
+    let books: BatchBooks = pairs.into_iter().zip(books).collect();
+
+    let quotes = requests
+        .iter()
+        .map(|request| {
+            let (fill, error) = match batch_quote(request, &books, &data) {
+                Ok(fill) => (Some(FillResponse::from(&fill)), None),
+                Err(err) => (None, Some(err)),
+            };
+            QuoteResponse {
+                pair: request.pair.clone(),
+                side: request.side,
+                amount: request.amount.clone(),
+                fill,
+                error,
+            }
+        })
+        .collect();
 
-    let avg_price = total_cost / target_amount;
+    Ok(flag_stale(web::Json(QuotesResponse {
+        last_update_ids: books.iter().filter_map(|(pair, book)| book.as_ref().ok().map(|book| (*pair, book.last_update_id()))).collect(),
+        quotes,
+    }), &stale))
+}
 
-    Ok(format!("Average Price: {}", avg_price))
+const DEFAULT_VALUATION_ASSET: &str = "USDT";
+/// Most assets a valuation request may hold.
+const MAX_HOLDINGS: usize = 100;
+
+#[derive(Deserialize)]
+struct LiquidationRequest {
+    /// Amount held per asset, e.g. `{"BTC": "1.5"}`.
+    holdings: BTreeMap<String, String>,
+    /// Asset the holdings are valued in.
+    quote: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ValuationResponse {
+    /// Books the holding is sold through, empty when it is already in the quote asset.
+    route: Vec<Pair>,
+    mark_value: String,
+    liquidation_value: String,
+    haircut: String,
+    haircut_bps: String,
+}
+
+#[derive(Serialize)]
+struct HoldingResponse {
+    asset: String,
+    amount: String,
+    #[serde(flatten)]
+    valuation: Option<ValuationResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LiquidationResponse {
+    quote: String,
+    /// Totals over the holdings that could be valued.
+    mark_value: String,
+    liquidation_value: String,
+    haircut: String,
+    haircut_bps: String,
+    /// Whether every holding could be valued.
+    complete: bool,
+    last_update_ids: HashMap<Pair, i64>,
+    holdings: Vec<HoldingResponse>,
+}
+
+/// Values each holding at mid and at what selling it into the books along the best
+/// route gets, all against the same copy of each book.
+#[post("/liquidation")]
+async fn post_liquidation(request: web::Json<LiquidationRequest>, stale: web::Query<StaleParams>, data: web::Data<AppState>) -> Result<impl Responder> {
+    if request.holdings.len() > MAX_HOLDINGS {
+        return Err(error::ErrorBadRequest(format!("At most {} holdings are allowed", MAX_HOLDINGS)));
+    }
+    let quote = request.quote.clone().unwrap_or_else(|| DEFAULT_VALUATION_ASSET.to_string());
+
+    // Routes of every holding not already in the quote asset
+    let planned = request
+        .holdings
+        .iter()
+        .map(|(asset, amount)| {
+            let amount = BigDecimal::from_str(amount).map_err(|_| "Invalid amount".to_string())?;
+            if amount <= BigDecimal::from(0) {
+                return Err("Amount must be positive".to_string());
+            }
+            let routes = if *asset == quote { Vec::new() } else { convert::routes(&data.pairs, asset, &quote, MAX_ROUTE_LEGS) };
+            if *asset != quote && routes.is_empty() {
+                return Err(format!("No route from {} to {}", asset, quote));
+            }
+            Ok((amount, routes))
+        })
+        .collect::<Vec<std::result::Result<(BigDecimal, Vec<SyntheticPair>), String>>>();
+
+    let mut pairs: Vec<Pair> = Vec::new();
+    for pair in planned.iter().flatten().flat_map(|(_, routes)| routes.iter().flat_map(|route| route.pairs())) {
+        if !pairs.contains(&pair) {
+            pairs.push(pair);
+        }
+    }
+    let stale = stale_books(&pairs, &stale, &data).await?;
+    let lookups = data.binance_client.get_each_orderbook(&pairs).await.map_err(error::ErrorServiceUnavailable)?;
+    let mut books = Vec::with_capacity(pairs.len());
+    let mut lookup_errors = HashMap::new();
+    for (pair, lookup) in pairs.iter().zip(lookups) {
+        match lookup {
+            Ok(orderbook) => books.push(orderbook),
+            Err(err) => {
+                lookup_errors.insert(*pair, err.to_string());
+            },
+        }
+    }
+
+    let mut total_mark = BigDecimal::from(0);
+    let mut total_liquidation = BigDecimal::from(0);
+    let mut holdings = Vec::with_capacity(planned.len());
+    for ((asset, amount), plan) in request.holdings.iter().zip(planned) {
+        let valued = plan.and_then(|(amount, routes)| {
+            if routes.is_empty() {
+                return Ok((Vec::new(), amount.clone(), amount));
+            }
+            // Like /convert, a book that cannot be read fails the holding with its own error
+            if let Some(err) = routes.iter().flat_map(|route| route.pairs()).find_map(|pair| lookup_errors.get(&pair)) {
+                return Err(err.clone());
+            }
+            let valuation = convert::value_holding(&routes, &books, &amount).map_err(|err| err.to_string())?;
+            Ok((valuation.conversion.route.pairs(), valuation.mark_value, valuation.liquidation_value))
+        });
+
+        let (valuation, error) = match valued {
+            Ok((route, mark_value, liquidation_value)) => {
+                total_mark += &mark_value;
+                total_liquidation += &liquidation_value;
+                let haircut = &mark_value - &liquidation_value;
+                let response = ValuationResponse {
+                    route,
+                    haircut_bps: plain(&haircut_bps(&haircut, &mark_value)),
+                    haircut: plain(&haircut),
+                    mark_value: plain(&mark_value),
+                    liquidation_value: plain(&liquidation_value),
+                };
+                (Some(response), None)
+            },
+            Err(err) => (None, Some(err)),
+        };
+        holdings.push(HoldingResponse {
+            asset: asset.clone(),
+            amount: amount.clone(),
+            valuation,
+            error,
+        });
+    }
+
+    let total_haircut = &total_mark - &total_liquidation;
+    Ok(flag_stale(web::Json(LiquidationResponse {
+        quote,
+        haircut_bps: plain(&haircut_bps(&total_haircut, &total_mark)),
+        haircut: plain(&total_haircut),
+        mark_value: plain(&total_mark),
+        liquidation_value: plain(&total_liquidation),
+        complete: holdings.iter().all(|holding| holding.error.is_none()),
+        last_update_ids: books.iter().map(|book| (book.symbol(), book.last_update_id())).collect(),
+        holdings,
+    }), &stale))
 }
 
 pub fn price_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(get_price_tips).service(get_execution_price);
+    cfg.app_data(web::JsonConfig::default().limit(MAX_BATCH_BYTES))
+        .service(get_price_tips)
+        .service(get_execution_price)
+        .service(get_history)
+        .service(get_candles)
+        .service(get_analytics)
+        .service(get_impact_curve)
+        .service(get_max_size)
+        .service(get_depth)
+        .service(get_depth_chart)
+        .service(get_conversion)
+        .service(post_quotes)
+        .service(post_liquidation);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use actix_web::{http::StatusCode, test, web, App};
+    use bigdecimal::BigDecimal;
+    use challenge::{
+        binance::{BinanceClient, BinanceConfig, ReplayConfig, ReplaySpeed},
+        capture::{Capture, CaptureConfig, RecordKind},
+        mock::{MockBinance, Scenario},
+        orderbook::{BookState, Pair},
+        synthetic::SyntheticPair,
+    };
+
+    use super::price_routes;
+    use crate::{config::Config, AppState};
+
+    const BTCUSDT: &str = r#"{"lastUpdateId":100,"bids":[["100.00","1.0"],["99.00","2.0"]],"asks":[["101.00","1.0"],["102.00","2.0"]]}"#;
+    const ETHUSDT: &str = r#"{"lastUpdateId":200,"bids":[["10.00","5.0"],["9.00","5.0"]],"asks":[["11.00","5.0"],["12.00","5.0"]]}"#;
+
+    /// App state over books replayed from one snapshot per `(symbol, body)`, with an
+    /// ETHBTC synthetic pair. Pairs without a snapshot stay bootstrapping.
+    async fn replayed_state(name: &str, snapshots: &[(&str, &str)]) -> web::Data<AppState> {
+        let dir = std::env::temp_dir().join(format!("challenge-prices-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let capture = Capture::start(CaptureConfig {
+            dir: dir.clone(),
+            max_file_bytes: 1024 * 1024,
+            max_file_age: std::time::Duration::from_secs(3600),
+        })
+        .unwrap();
+        capture.record(RecordKind::Connected, None, "ws://127.0.0.1/stream");
+        for (symbol, body) in snapshots {
+            capture.record(RecordKind::Snapshot, Some(symbol), body);
+        }
+        capture.finish();
+
+        let binance = Config::default().binance_config();
+        let (binance_client, stream_handle) = BinanceClient::replay(ReplayConfig {
+            source: dir.clone(),
+            speed: ReplaySpeed::AsFastAsPossible,
+            binance: binance.clone(),
+        })
+        .unwrap();
+        while !stream_handle.is_finished() {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        web::Data::new(AppState {
+            binance_client,
+            stream_handle,
+            pairs: binance.pairs,
+            synthetic_pairs: vec![SyntheticPair::new("ETH", "BTC", &[Pair::ETHUSDT, Pair::BTCUSDT]).unwrap()],
+        })
+    }
+
+    /// App state over books bootstrapped from `mock` that go stale right away.
+    async fn stale_state(mock: &MockBinance) -> web::Data<AppState> {
+        let binance = BinanceConfig {
+            endpoints: mock.endpoints(),
+            stale_after: std::time::Duration::from_millis(1),
+            ..Config::default().binance_config()
+        };
+        let (binance_client, stream_handle) = BinanceClient::new(binance.clone());
+        let mut stale = false;
+        for _ in 0..200 {
+            let status = binance_client.get_status().await.unwrap();
+            stale = status.iter().all(|status| status.state == BookState::Stale);
+            if stale {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(stale, "books never went stale");
+
+        web::Data::new(AppState {
+            binance_client,
+            stream_handle,
+            pairs: binance.pairs,
+            synthetic_pairs: vec![SyntheticPair::new("ETH", "BTC", &[Pair::ETHUSDT, Pair::BTCUSDT]).unwrap()],
+        })
+    }
+
+    struct Reply {
+        status: StatusCode,
+        /// The [`super::STALE_HEADER`] value, if any.
+        stale: Option<String>,
+        body: String,
+    }
+
+    impl Reply {
+        fn json(&self) -> serde_json::Value {
+            serde_json::from_str(&self.body).unwrap_or_else(|err| panic!("{}: {}", err, self.body))
+        }
+    }
+
+    async fn call(data: &web::Data<AppState>, req: test::TestRequest) -> Reply {
+        let app = test::init_service(App::new().app_data(data.clone()).service(web::scope("/prices").configure(price_routes))).await;
+        let res = test::call_service(&app, req.to_request()).await;
+        let status = res.status();
+        let stale = res.headers().get(super::STALE_HEADER).map(|value| value.to_str().unwrap().to_string());
+        let body = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        Reply { status, stale, body }
+    }
+
+    async fn get(data: &web::Data<AppState>, uri: &str) -> Reply {
+        call(data, test::TestRequest::get().uri(uri)).await
+    }
+
+    async fn post(data: &web::Data<AppState>, uri: &str, body: serde_json::Value) -> Reply {
+        call(data, test::TestRequest::post().uri(uri).set_json(body)).await
+    }
+
+    fn decimal(value: &serde_json::Value) -> BigDecimal {
+        BigDecimal::from_str(value.as_str().unwrap()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn serves_impact_curves() {
+        let data = replayed_state("impact", &[("BTCUSDT", BTCUSDT)]).await;
+
+        let reply = get(&data, "/prices/impact/BTCUSDT?side=buy&sizes=0.5,2,5").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(decimal(&body["best_price"]), BigDecimal::from(101));
+        assert_eq!(decimal(&body["points"][0]["average_price"]), BigDecimal::from(101));
+        assert_eq!(decimal(&body["points"][1]["average_price"]), BigDecimal::from_str("101.5").unwrap());
+        assert_eq!(body["points"][1]["complete"], true);
+        assert_eq!(body["points"][2]["complete"], false);
+
+        let reply = get(&data, "/prices/impact/BTCUSDT?side=sell&start=1&factor=2&steps=3").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.json()["points"].as_array().unwrap().len(), 3);
+
+        for uri in [
+            "/prices/impact/BTCUSDT?side=buy",
+            "/prices/impact/BTCUSDT?side=buy&sizes=0",
+            "/prices/impact/BTCUSDT?side=buy&sizes=abc",
+            "/prices/impact/BTCUSDT?side=buy&start=1&factor=1&steps=3",
+            "/prices/impact/BTCUSDT?side=buy&sizes=1&start=1&factor=2&steps=3",
+            "/prices/impact/XRPUSDT?side=buy&sizes=1",
+        ] {
+            assert_eq!(get(&data, uri).await.status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        // No snapshot was replayed for it
+        let reply = get(&data, "/prices/impact/ETHUSDT?side=buy&sizes=1").await;
+        assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn serves_max_sizes() {
+        let data = replayed_state("max-size", &[("BTCUSDT", BTCUSDT)]).await;
+
+        let reply = get(&data, "/prices/max-size/BTCUSDT?side=buy&slippage_bps=0").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(decimal(&body["quantity"]), BigDecimal::from(1));
+        assert_eq!(body["exhausted"], false);
+
+        let reply = get(&data, "/prices/max-size/BTCUSDT?side=buy&worst_price=200").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(decimal(&body["quantity"]), BigDecimal::from(3));
+        assert_eq!(decimal(&body["notional"]), BigDecimal::from(305));
+        assert_eq!(body["exhausted"], true);
+        // Decimals are printed without an exponent
+        assert!(!reply.body.contains('E'), "{}", reply.body);
+
+        for uri in [
+            "/prices/max-size/BTCUSDT?side=buy",
+            "/prices/max-size/BTCUSDT?side=buy&slippage_bps=10&worst_price=102",
+            "/prices/max-size/BTCUSDT?side=buy&slippage_bps=-1",
+            "/prices/max-size/BTCUSDT?side=buy&worst_price=abc",
+            "/prices/max-size/XRPUSDT?side=buy&slippage_bps=10",
+        ] {
+            assert_eq!(get(&data, uri).await.status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        let reply = get(&data, "/prices/max-size/ETHUSDT?side=buy&slippage_bps=10").await;
+        assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn serves_grouped_depth() {
+        let data = replayed_state("depth", &[("BTCUSDT", BTCUSDT)]).await;
+
+        let reply = get(&data, "/prices/depth/BTCUSDT?limit=1").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(body["last_update_id"], 100);
+        assert_eq!(body["bids"].as_array().unwrap().len(), 1);
+        assert_eq!(decimal(&body["bids"][0][0]), BigDecimal::from(100));
+        assert_eq!(decimal(&body["asks"][0][0]), BigDecimal::from(101));
+
+        // Asks round up into the same bucket
+        let reply = get(&data, "/prices/depth/BTCUSDT?group=5").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(body["asks"].as_array().unwrap().len(), 1);
+        assert_eq!(decimal(&body["asks"][0][0]), BigDecimal::from(105));
+        assert_eq!(decimal(&body["asks"][0][1]), BigDecimal::from(3));
+
+        for uri in [
+            "/prices/depth/BTCUSDT?limit=0",
+            "/prices/depth/BTCUSDT?limit=1001",
+            "/prices/depth/BTCUSDT?group=abc",
+            "/prices/depth/BTCUSDT?group=0",
+            "/prices/depth/XRPUSDT",
+        ] {
+            assert_eq!(get(&data, uri).await.status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        assert_eq!(get(&data, "/prices/depth/ETHUSDT").await.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn serves_depth_charts() {
+        let data = replayed_state("depth-chart", &[("BTCUSDT", BTCUSDT)]).await;
+
+        let reply = get(&data, "/prices/depth-chart/BTCUSDT").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(decimal(&body["mid"]), BigDecimal::from_str("100.5").unwrap());
+        let bids = body["bids"].as_array().unwrap();
+        assert_eq!(decimal(&bids.last().unwrap()["quantity"]), BigDecimal::from(3));
+
+        // Only the best level on each side is within 1% of mid
+        let reply = get(&data, "/prices/depth-chart/BTCUSDT?range_pct=1").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(decimal(&body["bids"].as_array().unwrap().last().unwrap()["quantity"]), BigDecimal::from(1));
+        assert_eq!(decimal(&body["asks"].as_array().unwrap().last().unwrap()["quantity"]), BigDecimal::from(1));
+
+        for uri in [
+            "/prices/depth-chart/BTCUSDT?points=0",
+            "/prices/depth-chart/BTCUSDT?points=1001",
+            "/prices/depth-chart/BTCUSDT?range_pct=0",
+            "/prices/depth-chart/BTCUSDT?range_pct=abc",
+            "/prices/depth-chart/XRPUSDT",
+        ] {
+            assert_eq!(get(&data, uri).await.status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        assert_eq!(get(&data, "/prices/depth-chart/ETHUSDT").await.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    fn average_price(reply: &Reply) -> BigDecimal {
+        let price = reply.body.strip_prefix("Average Price: ").unwrap_or_else(|| panic!("{}", reply.body));
+        BigDecimal::from_str(price).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn serves_execution_prices() {
+        let data = replayed_state("execution", &[("BTCUSDT", BTCUSDT), ("ETHUSDT", ETHUSDT)]).await;
+
+        let reply = get(&data, "/prices/execution-price?pair=BTCUSDT&operation=buy&amount=0.5").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(average_price(&reply), BigDecimal::from(101));
+        let reply = get(&data, "/prices/execution-price?pair=BTCUSDT&operation=sell&amount=2").await;
+        assert_eq!(average_price(&reply), BigDecimal::from_str("99.5").unwrap());
+
+        // Buying ETH with BTC sells BTC at 100 and buys ETH at 11
+        let reply = get(&data, "/prices/execution-price?pair=ETHBTC&operation=buy&amount=1").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(average_price(&reply), BigDecimal::from_str("0.11").unwrap());
+
+        for uri in [
+            "/prices/execution-price?pair=BTCUSDT&operation=buy&amount=0",
+            "/prices/execution-price?pair=BTCUSDT&operation=buy&amount=-1",
+            "/prices/execution-price?pair=BTCUSDT&operation=buy&amount=abc",
+            "/prices/execution-price?pair=BTCUSDT&operation=hold&amount=1",
+            "/prices/execution-price?pair=XRPUSDT&operation=buy&amount=1",
+            "/prices/execution-price?pair=ETHBTC&operation=buy&amount=0",
+        ] {
+            assert_eq!(get(&data, uri).await.status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        // More than the book holds
+        let reply = get(&data, "/prices/execution-price?pair=BTCUSDT&operation=buy&amount=5").await;
+        assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+        let reply = get(&data, "/prices/execution-price?pair=ETHBTC&operation=buy&amount=100").await;
+        assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+
+        // A synthetic pair needs every one of its books
+        let data = replayed_state("execution-missing", &[("BTCUSDT", BTCUSDT)]).await;
+        let reply = get(&data, "/prices/execution-price?pair=ETHBTC&operation=buy&amount=1").await;
+        assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn serves_conversions() {
+        let data = replayed_state("convert", &[("BTCUSDT", BTCUSDT), ("ETHUSDT", ETHUSDT)]).await;
+
+        let reply = get(&data, "/prices/convert?from=BTC&to=USDT&amount=1").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(decimal(&reply.json()["output"]), BigDecimal::from(100));
+
+        // Sold for 100 USDT, which buys 5 ETH at 11 and the rest at 12
+        let reply = get(&data, "/prices/convert?from=BTC&to=ETH&amount=1").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(body["route"], serde_json::json!(["BTCUSDT", "ETHUSDT"]));
+        assert_eq!(body["legs"][0]["buy"], "USDT");
+        assert_eq!(decimal(&body["legs"][1]["sell_amount"]), BigDecimal::from(100));
+        assert_eq!(decimal(&body["output"]), BigDecimal::from_str("8.75").unwrap());
+
+        for uri in [
+            "/prices/convert?from=BTC&to=ETH&amount=0",
+            "/prices/convert?from=BTC&to=ETH&amount=abc",
+            "/prices/convert?from=BTC&to=BTC&amount=1",
+            "/prices/convert?from=XRP&to=USDT&amount=1",
+        ] {
+            assert_eq!(get(&data, uri).await.status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        assert_eq!(get(&data, "/prices/convert?from=BTC&to=USDT&amount=5").await.status, StatusCode::SERVICE_UNAVAILABLE);
+
+        let data = replayed_state("convert-missing", &[("BTCUSDT", BTCUSDT)]).await;
+        let reply = get(&data, "/prices/convert?from=BTC&to=ETH&amount=1").await;
+        assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(reply.body.contains("not ready"), "{}", reply.body);
+    }
+
+    #[actix_web::test]
+    async fn serves_batch_quotes() {
+        let data = replayed_state("quotes", &[("BTCUSDT", BTCUSDT), ("ETHUSDT", ETHUSDT)]).await;
+        let quotes = serde_json::json!([
+            {"pair": "BTCUSDT", "side": "buy", "amount": "0.5"},
+            {"pair": "ETHBTC", "side": "buy", "amount": "1"},
+            {"pair": "XRPUSDT", "side": "buy", "amount": "1"},
+            {"pair": "BTCUSDT", "side": "sell", "amount": "abc"},
+        ]);
+
+        let reply = post(&data, "/prices/quotes", quotes.clone()).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(body["last_update_ids"]["BTCUSDT"], 100);
+        assert_eq!(body["last_update_ids"]["ETHUSDT"], 200);
+        assert_eq!(decimal(&body["quotes"][0]["average_price"]), BigDecimal::from(101));
+        assert_eq!(decimal(&body["quotes"][1]["average_price"]), BigDecimal::from_str("0.11").unwrap());
+        // Bad items carry an error instead of failing the batch
+        assert_eq!(body["quotes"][2]["error"], "Invalid pair");
+        assert_eq!(body["quotes"][3]["error"], "Invalid amount");
+
+        // Each item gets the error of the book it needs
+        let data = replayed_state("quotes-missing", &[("BTCUSDT", BTCUSDT)]).await;
+        let body = post(&data, "/prices/quotes", quotes).await.json();
+        assert!(body["quotes"][0]["error"].is_null());
+        assert!(body["quotes"][1]["error"].as_str().unwrap().contains("not ready"), "{}", body);
+
+        let too_many = vec![serde_json::json!({"pair": "BTCUSDT", "side": "buy", "amount": "1"}); 1001];
+        assert_eq!(post(&data, "/prices/quotes", serde_json::json!(too_many)).await.status, StatusCode::BAD_REQUEST);
+        assert_eq!(post(&data, "/prices/quotes", serde_json::json!({"pair": "BTCUSDT"})).await.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn serves_liquidation_values() {
+        let data = replayed_state("liquidation", &[("BTCUSDT", BTCUSDT), ("ETHUSDT", ETHUSDT)]).await;
+        let holdings = serde_json::json!({"holdings": {"BTC": "1", "ETH": "2", "USDT": "50"}});
+
+        let reply = post(&data, "/prices/liquidation", holdings.clone()).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(body["quote"], "USDT");
+        assert_eq!(body["complete"], true);
+        // Marked at mid, sold at the best bids
+        assert_eq!(decimal(&body["mark_value"]), BigDecimal::from_str("171.5").unwrap());
+        assert_eq!(decimal(&body["liquidation_value"]), BigDecimal::from(170));
+        assert_eq!(body["holdings"][2]["route"], serde_json::json!([]));
+
+        let reply = post(&data, "/prices/liquidation", serde_json::json!({"holdings": {"BTC": "0", "XRP": "1"}})).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let body = reply.json();
+        assert_eq!(body["complete"], false);
+        assert_eq!(body["holdings"][0]["error"], "Amount must be positive");
+        assert_eq!(body["holdings"][1]["error"], "No route from XRP to USDT");
+
+        // A holding whose book is not ready says so, the others are still valued
+        let data = replayed_state("liquidation-missing", &[("BTCUSDT", BTCUSDT)]).await;
+        let body = post(&data, "/prices/liquidation", holdings).await.json();
+        assert_eq!(body["complete"], false);
+        assert!(body["holdings"][0]["error"].is_null());
+        assert!(body["holdings"][1]["error"].as_str().unwrap().contains("ETHUSDT is not ready"), "{}", body);
+        assert_eq!(decimal(&body["liquidation_value"]), BigDecimal::from(150));
+
+        let too_many = (0..101).map(|i| (format!("A{}", i), "1".to_string())).collect::<std::collections::BTreeMap<String, String>>();
+        let reply = post(&data, "/prices/liquidation", serde_json::json!({"holdings": too_many})).await;
+        assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn guards_stale_books() {
+        let scenario = Scenario::default()
+            .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+            .snapshot("ETHUSDT", 200, &[("10.00", "5.0")], &[("11.00", "5.0")]);
+        let mock = MockBinance::start(scenario).await.unwrap();
+        let data = stale_state(&mock).await;
+
+        // Rejected by default, naming the stale book
+        let reply = get(&data, "/prices/price-tips/BTCUSDT").await;
+        assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(reply.body.contains("BTCUSDT is stale"), "{}", reply.body);
+        assert_eq!(get(&data, "/prices/execution-price?pair=BTCUSDT&operation=buy&amount=0.5").await.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(get(&data, "/prices/convert?from=BTC&to=ETH&amount=0.1&stale=reject").await.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(get(&data, "/prices/price-tips/BTCUSDT?stale=maybe").await.status, StatusCode::BAD_REQUEST);
+
+        // Served when flagged, with the books used in the header
+        let reply = get(&data, "/prices/price-tips/BTCUSDT?stale=flag").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.stale.as_deref(), Some("BTCUSDT"));
+        let reply = get(&data, "/prices/execution-price?pair=ETHBTC&operation=buy&amount=1&stale=flag").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.stale.as_deref(), Some("BTCUSDT,ETHUSDT"));
+        for uri in [
+            "/prices/impact/BTCUSDT?side=buy&sizes=0.5&stale=flag",
+            "/prices/max-size/BTCUSDT?side=buy&slippage_bps=10&stale=flag",
+            "/prices/depth/BTCUSDT?stale=flag",
+            "/prices/depth-chart/BTCUSDT?stale=flag",
+            "/prices/analytics/BTCUSDT?stale=flag",
+        ] {
+            let reply = get(&data, uri).await;
+            assert_eq!(reply.status, StatusCode::OK, "{}", uri);
+            assert_eq!(reply.stale.as_deref(), Some("BTCUSDT"), "{}", uri);
+        }
+        let reply = get(&data, "/prices/convert?from=BTC&to=ETH&amount=0.1&stale=flag").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.stale.as_deref(), Some("BTCUSDT,ETHUSDT"));
+
+        let quotes = serde_json::json!([{"pair": "ETHUSDT", "side": "sell", "amount": "1"}]);
+        assert_eq!(post(&data, "/prices/quotes", quotes.clone()).await.status, StatusCode::SERVICE_UNAVAILABLE);
+        let reply = post(&data, "/prices/quotes?stale=flag", quotes).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.stale.as_deref(), Some("ETHUSDT"));
+        let holdings = serde_json::json!({"holdings": {"BTC": "0.5"}});
+        assert_eq!(post(&data, "/prices/liquidation", holdings.clone()).await.status, StatusCode::SERVICE_UNAVAILABLE);
+        let reply = post(&data, "/prices/liquidation?stale=flag", holdings).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.stale.as_deref(), Some("BTCUSDT"));
+
+        // Fresh books carry no header
+        let data = replayed_state("fresh", &[("BTCUSDT", BTCUSDT)]).await;
+        let reply = get(&data, "/prices/price-tips/BTCUSDT?stale=flag").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.stale, None);
+    }
 }
@@ -0,0 +1,125 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error, get,
+    middleware::Next,
+    web, HttpResponse, Result,
+};
+use challenge::{
+    metrics::{metrics, symbol_label},
+    orderbook::{BookState, BookStatus},
+};
+use serde::Serialize;
+use tracing::{debug, field, info_span, Instrument};
+
+use crate::AppState;
+
+#[derive(Serialize)]
+struct BookStatusResponse {
+    symbol: String,
+    state: BookState,
+    last_update_id: Option<i64>,
+    last_diff_age_ms: Option<u64>,
+    last_event_age_ms: Option<u64>,
+}
+
+impl From<BookStatus> for BookStatusResponse {
+    fn from(status: BookStatus) -> BookStatusResponse {
+        BookStatusResponse {
+            symbol: symbol_label(status.symbol),
+            state: status.state,
+            last_update_id: status.last_update_id,
+            last_diff_age_ms: status.last_diff_age.map(|age| age.as_millis() as u64),
+            last_event_age_ms: status.last_event_age.map(|age| age.as_millis() as u64),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    healthy: bool,
+    stream_running: bool,
+    websocket_connected: bool,
+    replay: bool,
+    books: Vec<BookStatusResponse>,
+}
+
+#[derive(Serialize)]
+struct ReadyResponse {
+    ready: bool,
+    books: Vec<BookStatusResponse>,
+}
+
+/// 200 with `body` when `ok`, 503 otherwise, so probes only need the status code.
+fn probe_response(ok: bool, body: impl Serialize) -> HttpResponse {
+    if ok {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Records how long each request took, by route pattern so `/prices/depth/BTCUSDT`
+/// and `/prices/depth/ETHUSDT` share a series, and serves it within a span.
+pub async fn record_latency(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>> {
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_owned());
+    let span = info_span!("http_request", %method, %route, path = req.path(), status = field::Empty);
+    let timer = std::time::Instant::now();
+
+    let res = next.call(req).instrument(span.clone()).await;
+    let status = match &res {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+    let elapsed = timer.elapsed();
+    metrics()
+        .http_request_seconds
+        .with_label_values(&[method.as_str(), route.as_str(), status.as_str()])
+        .observe(elapsed.as_secs_f64());
+    span.record("status", status.as_u16());
+    span.in_scope(|| debug!(elapsed_ms = elapsed.as_secs_f64() * 1000.0, "Served request"));
+    res
+}
+
+/// Liveness: the task feeding the books is running and its websocket is connected.
+/// A replay keeps serving its books once every record is fed, so it is healthy as
+/// long as the manager answers.
+#[get("/health")]
+async fn get_health(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let stream_running = !data.stream_handle.is_finished();
+    let websocket_connected = data.binance_client.is_connected();
+    let replay = data.binance_client.is_replay();
+    let books = data.binance_client.get_status().await.map_err(error::ErrorServiceUnavailable)?;
+
+    let healthy = replay || (stream_running && websocket_connected);
+    Ok(probe_response(healthy, HealthResponse {
+        healthy,
+        stream_running,
+        websocket_connected,
+        replay,
+        books: books.into_iter().map(BookStatusResponse::from).collect(),
+    }))
+}
+
+/// Readiness: every configured book is live and fresh.
+#[get("/ready")]
+async fn get_ready(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let books = data.binance_client.get_status().await.map_err(error::ErrorServiceUnavailable)?;
+
+    let ready = !books.is_empty() && books.iter().all(|book| book.state == BookState::Live);
+    Ok(probe_response(ready, ReadyResponse {
+        ready,
+        books: books.into_iter().map(BookStatusResponse::from).collect(),
+    }))
+}
+
+#[get("/metrics")]
+async fn get_metrics() -> Result<HttpResponse> {
+    let body = metrics().render().map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().content_type(prometheus::TEXT_FORMAT).body(body))
+}
+
+pub fn status_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_health).service(get_ready).service(get_metrics);
+}
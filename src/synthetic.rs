@@ -0,0 +1,289 @@
+use std::io::{Error, ErrorKind};
+
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::{
+    binance::assets_for_pair,
+    orderbook::{divide, slippage_bps, Fill, OrderBook, Pair, Side, Tips},
+};
+
+/// A tracked book as one step of a synthetic pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leg {
+    pub pair: Pair,
+    /// The step goes from the book's quote asset to its base asset, so buying on
+    /// this leg sells on the book.
+    pub inverted: bool,
+}
+
+impl Leg {
+    /// Amount a fill on this leg hands to the next one, in the leg's quote asset as
+    /// the synthetic pair sees it.
+    pub fn output(&self, fill: &Fill) -> BigDecimal {
+        if self.inverted {
+            fill.filled.clone()
+        } else {
+            fill.notional.clone()
+        }
+    }
+}
+
+/// A pair that is not traded directly, priced by chaining tracked books. ETHBTC, for
+/// example, goes from ETH to USDT on ETHUSDT and from USDT to BTC on BTCUSDT.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticPair {
+    symbol: String,
+    legs: Vec<Leg>,
+}
+
+impl SyntheticPair {
+    /// Chains `pairs` from `base` to `quote`, working out which way round each one is used.
+    pub fn new(base: &str, quote: &str, pairs: &[Pair]) -> Result<SyntheticPair, Error> {
+        if pairs.len() < 2 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{}{} needs at least two legs", base, quote)));
+        }
+        SyntheticPair::route(base, quote, pairs)
+    }
+
+    /// Like [`SyntheticPair::new`], but also takes a single book, for conversions
+    /// that go from one asset to another directly.
+    pub fn route(base: &str, quote: &str, pairs: &[Pair]) -> Result<SyntheticPair, Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+        if pairs.is_empty() {
+            return Err(invalid(format!("{}{} needs a leg", base, quote)));
+        }
+
+        let mut asset = base;
+        let mut legs = Vec::with_capacity(pairs.len());
+        for pair in pairs {
+            let (pair_base, pair_quote) = assets_for_pair(*pair).ok_or_else(|| invalid(format!("Unknown pair {:?}", pair)))?;
+            let inverted = if pair_base == asset {
+                false
+            } else if pair_quote == asset {
+                true
+            } else {
+                return Err(invalid(format!("{:?} does not trade {}", pair, asset)));
+            };
+            asset = if inverted { pair_base } else { pair_quote };
+            legs.push(Leg { pair: *pair, inverted });
+        }
+        if asset != quote {
+            return Err(invalid(format!("Legs of {}{} end in {} instead of {}", base, quote, asset, quote)));
+        }
+
+        Ok(SyntheticPair {
+            symbol: format!("{}{}", base, quote),
+            legs,
+        })
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn legs(&self) -> &[Leg] {
+        &self.legs
+    }
+
+    /// Pairs whose books are needed, in leg order.
+    pub fn pairs(&self) -> Vec<Pair> {
+        self.legs.iter().map(|leg| leg.pair).collect()
+    }
+
+    fn check_books(&self, books: &[OrderBook]) -> Result<(), Error> {
+        if books.len() != self.legs.len() || self.legs.iter().zip(books).any(|(leg, book)| leg.pair != book.symbol()) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Expected the books of {:?}", self.pairs())));
+        }
+        Ok(())
+    }
+
+    /// Best bid and ask, each as `(price, quantity)` in the synthetic base. The
+    /// quantity is what the top level of every leg can take at once.
+    pub fn tips(&self, books: &[OrderBook]) -> Result<Tips, Error> {
+        self.check_books(books)?;
+
+        let mut bid = (BigDecimal::from(1), None::<BigDecimal>);
+        let mut ask = (BigDecimal::from(1), None::<BigDecimal>);
+        for (leg, book) in self.legs.iter().zip(books) {
+            let (leg_bid, leg_ask) = leg_tips(leg, book)?;
+            for ((price, quantity), (leg_price, leg_quantity)) in [(&mut bid, leg_bid), (&mut ask, leg_ask)] {
+                // The leg quantity is in this leg's base, which costs `price` synthetic base
+                let available = divide(&leg_quantity, price);
+                if quantity.as_ref().is_none_or(|quantity| available < *quantity) {
+                    *quantity = Some(available);
+                }
+                *price *= leg_price;
+            }
+        }
+
+        Ok((
+            (bid.0, bid.1.unwrap_or_else(BigDecimal::zero)),
+            (ask.0, ask.1.unwrap_or_else(BigDecimal::zero)),
+        ))
+    }
+
+    /// Product of the legs' mids, each turned the way its leg is used.
+    pub fn mid(&self, books: &[OrderBook]) -> Result<BigDecimal, Error> {
+        self.check_books(books)?;
+
+        let mut mid = BigDecimal::from(1);
+        for (leg, book) in self.legs.iter().zip(books) {
+            let leg_mid = book.mid()?;
+            if leg.inverted {
+                mid *= divide(&BigDecimal::from(1), &leg_mid);
+            } else {
+                mid *= leg_mid;
+            }
+        }
+        Ok(mid)
+    }
+
+    /// The fill on each leg's book for `quantity` synthetic base, walking the depth of
+    /// every leg in turn with the amount the previous one produced. Errors when any
+    /// leg runs out.
+    pub fn leg_fills(&self, side: Side, quantity: &BigDecimal, books: &[OrderBook]) -> Result<Vec<Fill>, Error> {
+        self.check_books(books)?;
+
+        let mut amount = quantity.clone();
+        let mut fills = Vec::with_capacity(self.legs.len());
+        for (leg, book) in self.legs.iter().zip(books) {
+            let (fill, complete) = if leg.inverted {
+                // Getting the book's quote means selling its base, and the other way round
+                let book_side = match side {
+                    Side::Buy => Side::Sell,
+                    Side::Sell => Side::Buy,
+                };
+                let fill = book.execution_for_notional(book_side, &amount)?;
+                let complete = fill.notional >= amount;
+                (fill, complete)
+            } else {
+                let fill = book.execution(side, &amount)?;
+                let complete = fill.is_complete();
+                (fill, complete)
+            };
+            if !complete {
+                return Err(Error::other(format!("Not enough depth on {:?}", leg.pair)));
+            }
+            amount = leg.output(&fill);
+            fills.push(fill);
+        }
+        Ok(fills)
+    }
+
+    /// Fill of `quantity` synthetic base across all legs, see [`SyntheticPair::leg_fills`].
+    /// `worst_price` combines the worst level touched on each leg.
+    pub fn execution(&self, side: Side, quantity: &BigDecimal, books: &[OrderBook]) -> Result<Fill, Error> {
+        let ((bid, _), (ask, _)) = self.tips(books)?;
+        let fills = self.leg_fills(side, quantity, books)?;
+
+        let mut worst_price = BigDecimal::from(1);
+        for (leg, fill) in self.legs.iter().zip(fills.iter()) {
+            if leg.inverted {
+                worst_price *= divide(&BigDecimal::from(1), &fill.worst_price);
+            } else {
+                worst_price *= &fill.worst_price;
+            }
+        }
+        // What the last leg produced, in the synthetic quote
+        let amount = match (self.legs.last(), fills.last()) {
+            (Some(leg), Some(fill)) => leg.output(fill),
+            _ => return Err(Error::other("No legs")),
+        };
+
+        let average_price = divide(&amount, quantity);
+        let best = match side {
+            Side::Buy => ask,
+            Side::Sell => bid,
+        };
+        Ok(Fill {
+            quantity: quantity.clone(),
+            filled: quantity.clone(),
+            slippage_bps: slippage_bps(side, &best, &average_price),
+            notional: amount,
+            average_price,
+            worst_price,
+        })
+    }
+}
+
+/// Tips of a leg's book as seen from the synthetic pair. Inverted, the book's best
+/// ask becomes the bid, priced in base per quote and sized in the book's quote asset.
+fn leg_tips(leg: &Leg, book: &OrderBook) -> Result<Tips, Error> {
+    let ((bid, bid_quantity), (ask, ask_quantity)) = book.get_tips()?;
+    if !leg.inverted {
+        return Ok(((bid, bid_quantity), (ask, ask_quantity)));
+    }
+
+    let one = BigDecimal::from(1);
+    Ok((
+        (divide(&one, &ask), &ask * ask_quantity),
+        (divide(&one, &bid), &bid * bid_quantity),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use super::{Leg, SyntheticPair};
+    use crate::orderbook::{OrderBook, Pair, Side};
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    fn books() -> Vec<OrderBook> {
+        vec![
+            OrderBook::new(
+                Pair::ETHUSDT,
+                vec![(decimal("10"), decimal("5")), (decimal("9"), decimal("10"))],
+                vec![(decimal("11"), decimal("4")), (decimal("12"), decimal("10"))],
+                1,
+            ),
+            OrderBook::new(Pair::BTCUSDT, vec![(decimal("100"), decimal("1"))], vec![(decimal("101"), decimal("1")), (decimal("102"), decimal("1"))], 1),
+        ]
+    }
+
+    #[test]
+    fn chains_legs_from_base_to_quote() {
+        let ethbtc = SyntheticPair::new("ETH", "BTC", &[Pair::ETHUSDT, Pair::BTCUSDT]).unwrap();
+        assert_eq!(ethbtc.symbol(), "ETHBTC");
+        assert_eq!(ethbtc.legs(), &[Leg { pair: Pair::ETHUSDT, inverted: false }, Leg { pair: Pair::BTCUSDT, inverted: true }]);
+
+        assert!(SyntheticPair::new("ETH", "BTC", &[Pair::ETHUSDT]).is_err());
+        assert!(SyntheticPair::new("ETH", "BTC", &[Pair::BTCUSDT, Pair::ETHUSDT]).is_err());
+        assert!(SyntheticPair::new("ETH", "USDC", &[Pair::ETHUSDT, Pair::BTCUSDT]).is_err());
+    }
+
+    #[test]
+    fn prices_through_both_books() {
+        let ethbtc = SyntheticPair::new("ETH", "BTC", &[Pair::ETHUSDT, Pair::BTCUSDT]).unwrap();
+        let books = books();
+
+        let ((bid, bid_quantity), (ask, ask_quantity)) = ethbtc.tips(&books).unwrap();
+        // Sell ETH at 10 USDT and buy BTC at 101 USDT
+        assert_eq!(bid, decimal("0.099009901"));
+        assert_eq!(bid_quantity, decimal("5"));
+        assert_eq!(ask, decimal("0.11"));
+        assert_eq!(ask_quantity, decimal("4"));
+        // 10.5 times 1 / 100.5, the inverse rounded to ten places
+        assert_eq!(ethbtc.mid(&books).unwrap(), decimal("0.1044776124"));
+
+        // 56 USDT from two ETHUSDT levels, raised by selling 0.56 BTC
+        let fill = ethbtc.execution(Side::Buy, &decimal("5"), &books).unwrap();
+        assert_eq!(fill.notional, decimal("0.56"));
+        assert_eq!(fill.average_price, decimal("0.112"));
+        assert_eq!(fill.worst_price, decimal("0.12"));
+        assert_eq!(fill.slippage_bps, decimal("181.8181818182"));
+
+        let fill = ethbtc.execution(Side::Sell, &decimal("5"), &books).unwrap();
+        assert_eq!(fill.notional, decimal("0.4950495049"));
+
+        // 20 ETH is more than ETHUSDT holds, and the 116 USDT for 10 ETH more than one BTC raises
+        assert!(ethbtc.execution(Side::Buy, &decimal("20"), &books).is_err());
+        assert!(ethbtc.execution(Side::Buy, &decimal("10"), &books).is_err());
+        assert!(ethbtc.tips(&books[..1]).is_err());
+    }
+}
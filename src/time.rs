@@ -0,0 +1,7 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Wall clock time in microseconds since the Unix epoch, the unit of every internal
+/// timestamp.
+pub fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or_default()
+}
@@ -0,0 +1,438 @@
+use std::{path::Path, str::FromStr, time::Duration};
+
+use bigdecimal::BigDecimal;
+use challenge::{
+    binance::{BinanceClient, BinanceConfig, ReconnectPolicy},
+    candles::CandleConfig,
+    capture::{capture_files, CaptureConfig, CaptureReader, RecordKind},
+    history::HistoryConfig,
+    metrics::metrics,
+    mock::{MockBinance, Scenario, Step},
+    orderbook::{load_orderbook, save_orderbook, InvariantConfig, OrderBook, Pair, PersistenceConfig, Tips, DEFAULT_STALE_AFTER},
+};
+
+fn config_for(mock: &MockBinance, pairs: Vec<Pair>) -> BinanceConfig {
+    BinanceConfig {
+        endpoints: mock.endpoints(),
+        pairs,
+        snapshot_depth: 1000,
+        reconnect: ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            max_attempts: Some(5),
+        },
+        capture: None,
+        persistence: None,
+        history: HistoryConfig::default(),
+        candles: CandleConfig::default(),
+        stale_after: DEFAULT_STALE_AFTER,
+        verification: None,
+        invariants: InvariantConfig::default(),
+    }
+}
+
+fn client_for(mock: &MockBinance, pairs: Vec<Pair>) -> BinanceClient {
+    client_with_capture(mock, pairs, None)
+}
+
+fn client_with_capture(mock: &MockBinance, pairs: Vec<Pair>, capture: Option<CaptureConfig>) -> BinanceClient {
+    let (client, _handle) = BinanceClient::new(BinanceConfig {
+        capture,
+        ..config_for(mock, pairs)
+    });
+    client
+}
+
+fn client_with_persistence(mock: &MockBinance, pairs: Vec<Pair>, persistence: PersistenceConfig) -> BinanceClient {
+    let (client, _handle) = BinanceClient::new(BinanceConfig {
+        persistence: Some(persistence),
+        ..config_for(mock, pairs)
+    });
+    client
+}
+
+fn level(price: &str, quantity: &str) -> (BigDecimal, BigDecimal) {
+    (BigDecimal::from_str(price).unwrap(), BigDecimal::from_str(quantity).unwrap())
+}
+
+/// Polls the client until the tips match, panicking after a few seconds.
+async fn wait_for_tips(client: &BinanceClient, pair: Pair, bid: (&str, &str), ask: (&str, &str)) -> Tips {
+    let expected = (level(bid.0, bid.1), level(ask.0, ask.1));
+    let mut last = None;
+    for _ in 0..200 {
+        match client.get_tips(pair).await {
+            Ok(tips) if tips == expected => return tips,
+            res => last = Some(res),
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Tips never became {:?}, last seen {:?}", expected, last);
+}
+
+#[actix_web::test]
+async fn bootstraps_and_applies_diffs() {
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .session(vec![
+            // Already covered by the snapshot
+            Step::diff("BTCUSDT", 90, 95, &[("99.00", "5.0")], &[]),
+            Step::WaitForSnapshots { count: 1 },
+            Step::diff("BTCUSDT", 96, 102, &[("100.50", "2.0")], &[]),
+            Step::diff("BTCUSDT", 103, 104, &[], &[("101.00", "0"), ("100.80", "4.0")]),
+            // Duplicate delivery
+            Step::diff("BTCUSDT", 103, 104, &[("100.50", "9.0")], &[]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let client = client_for(&mock, vec![Pair::BTCUSDT]);
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.50", "2.0"), ("100.80", "4.0")).await;
+    let bids = client.get_bids(Pair::BTCUSDT).await.unwrap();
+    assert_eq!(bids, vec![level("100.50", "2.0"), level("100.00", "1.0")]);
+    let orderbook = client.get_orderbook(Pair::BTCUSDT).await.unwrap();
+    assert_eq!(orderbook.last_update_id(), 104);
+    assert_eq!(orderbook.bids(), &bids);
+    assert!(client.get_tips(Pair::ETHUSDT).await.is_err());
+
+    // An untracked pair fails on its own
+    let books = client.get_each_orderbook(&[Pair::ETHUSDT, Pair::BTCUSDT]).await.unwrap();
+    assert!(books[0].is_err());
+    assert_eq!(books[1].as_ref().unwrap().last_update_id(), 104);
+    assert!(client.get_orderbooks(&[Pair::ETHUSDT, Pair::BTCUSDT]).await.is_err());
+    assert_eq!(mock.snapshot_requests(), 1);
+}
+
+#[actix_web::test]
+async fn tracks_multiple_pairs() {
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .snapshot("ETHUSDT", 500, &[("10.00", "1.0")], &[("11.00", "1.0")])
+        .session(vec![
+            Step::WaitForSnapshots { count: 2 },
+            Step::diff("ETHUSDT", 501, 502, &[("10.50", "1.5")], &[]),
+            Step::diff("BTCUSDT", 101, 102, &[], &[("100.90", "0.5")]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let client = client_for(&mock, vec![Pair::BTCUSDT, Pair::ETHUSDT]);
+
+    wait_for_tips(&client, Pair::ETHUSDT, ("10.50", "1.5"), ("11.00", "1.0")).await;
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.00", "1.0"), ("100.90", "0.5")).await;
+}
+
+#[actix_web::test]
+async fn skips_malformed_frames() {
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .session(vec![
+            Step::WaitForSnapshots { count: 1 },
+            Step::frame("not json"),
+            Step::frame(r#"{"result":null,"id":1}"#),
+            Step::frame(r#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","s":"BTCUSDT","U":101}}"#),
+            Step::frame(r#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","s":"BTCUSDT","U":101,"u":102,"b":[["abc","1"]],"a":[]}}"#),
+            Step::frame(r#"{"stream":"nosuchpair@depth","data":{"e":"depthUpdate","s":"NOSUCHPAIR","U":1,"u":2,"b":[],"a":[]}}"#),
+            Step::diff("BTCUSDT", 101, 102, &[("100.20", "1.0")], &[]),
+            // Event time out of range, the diff still applies
+            Step::frame(r#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","E":18446744073709551615,"s":"BTCUSDT","U":103,"u":103,"b":[["100.30","1.0"]],"a":[]}}"#),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let client = client_for(&mock, vec![Pair::BTCUSDT]);
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.30", "1.0"), ("101.00", "1.0")).await;
+    assert_eq!(mock.connections(), 1);
+    // Symbols that are not known pairs share a single series
+    let text = metrics().render().unwrap();
+    assert!(!text.contains("NOSUCHPAIR"));
+    assert!(text.contains("ws_messages_failed_total{symbol=\"unknown\"}"));
+}
+
+#[actix_web::test]
+async fn resyncs_after_gap() {
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .snapshot("BTCUSDT", 110, &[("100.00", "3.0")], &[("101.00", "3.0")])
+        .session(vec![
+            Step::WaitForSnapshots { count: 1 },
+            Step::diff("BTCUSDT", 101, 102, &[("100.10", "1.0")], &[]),
+            // 103 to 105 never arrive
+            Step::diff("BTCUSDT", 106, 108, &[("100.30", "1.0")], &[]),
+            Step::WaitForSnapshots { count: 2 },
+            Step::diff("BTCUSDT", 109, 112, &[("100.20", "2.0")], &[]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let client = client_for(&mock, vec![Pair::BTCUSDT]);
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.20", "2.0"), ("101.00", "3.0")).await;
+    assert_eq!(mock.snapshot_requests(), 2);
+    assert_eq!(mock.connections(), 1);
+}
+
+#[actix_web::test]
+async fn reconnects_after_disconnect_from_scenario_file() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios/reconnect.json");
+    let mock = MockBinance::start(Scenario::from_file(&path).unwrap()).await.unwrap();
+    let client = client_for(&mock, vec![Pair::BTCUSDT]);
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("200.00", "1.0"), ("200.50", "3.0")).await;
+    assert_eq!(mock.connections(), 2);
+    assert_eq!(mock.snapshot_requests(), 2);
+}
+
+#[actix_web::test]
+async fn replays_captured_traffic_as_a_scenario() {
+    let dir = std::env::temp_dir().join(format!("challenge-mock-replay-capture-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // Record a session with a reconnect against a scripted mock
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios/reconnect.json");
+    let mock = MockBinance::start(Scenario::from_file(&path).unwrap()).await.unwrap();
+    let capture = CaptureConfig {
+        dir: dir.clone(),
+        max_file_bytes: 1024 * 1024,
+        max_file_age: Duration::from_secs(3600),
+    };
+    let client = client_with_capture(&mock, vec![Pair::BTCUSDT], Some(capture));
+    wait_for_tips(&client, Pair::BTCUSDT, ("200.00", "1.0"), ("200.50", "3.0")).await;
+
+    // Two connections, two snapshots and two diffs, readable once the writer flushes
+    let mut scenario = Scenario::default();
+    for _ in 0..100 {
+        scenario = Scenario::from_capture(&dir).unwrap();
+        let frames = scenario.sessions.iter().flatten().filter(|step| matches!(step, Step::Frame { .. })).count();
+        if scenario.sessions.len() == 2 && scenario.snapshots["BTCUSDT"].len() == 2 && frames == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(scenario.sessions.len(), 2);
+    assert_eq!(scenario.sessions[0].last(), Some(&Step::Disconnect));
+
+    // Playing the capture back takes a fresh client through the same reconnect
+    let replayed = MockBinance::start(scenario).await.unwrap();
+    let client = client_for(&replayed, vec![Pair::BTCUSDT]);
+    wait_for_tips(&client, Pair::BTCUSDT, ("200.00", "1.0"), ("200.50", "3.0")).await;
+    assert_eq!(replayed.connections(), 2);
+    assert_eq!(replayed.snapshot_requests(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[actix_web::test]
+async fn gives_up_after_max_attempts() {
+    let mock = MockBinance::start(Scenario::default()).await.unwrap();
+    let config = config_for(&mock, vec![Pair::BTCUSDT]);
+    mock.stop().await;
+
+    let (client, handle) = BinanceClient::new(config);
+    tokio::time::timeout(Duration::from_secs(5), handle).await.unwrap().unwrap();
+    assert!(!client.is_connected());
+    assert!(client.get_tips(Pair::BTCUSDT).await.is_err());
+}
+
+#[actix_web::test]
+async fn captures_raw_traffic() {
+    let dir = std::env::temp_dir().join(format!("challenge-mock-capture-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .session(vec![
+            Step::WaitForSnapshots { count: 1 },
+            Step::frame("not json"),
+            Step::diff("BTCUSDT", 101, 102, &[("100.20", "1.0")], &[]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let capture = CaptureConfig {
+        dir: dir.clone(),
+        max_file_bytes: 1024 * 1024,
+        max_file_age: Duration::from_secs(3600),
+    };
+    let client = client_with_capture(&mock, vec![Pair::BTCUSDT], Some(capture));
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.20", "1.0"), ("101.00", "1.0")).await;
+
+    // Records become readable once the writer flushes
+    let mut kinds = Vec::new();
+    for _ in 0..100 {
+        kinds = capture_files(&dir)
+            .unwrap()
+            .iter()
+            .flat_map(|file| CaptureReader::open(file).unwrap())
+            .filter_map(|record| record.ok())
+            .map(|record| record.kind)
+            .collect::<Vec<RecordKind>>();
+        if kinds.len() == 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(kinds[0], RecordKind::Connected);
+    assert!(kinds.contains(&RecordKind::Snapshot));
+    assert_eq!(kinds.iter().filter(|kind| **kind == RecordKind::Frame).count(), 2);
+
+    // Shutting down completes the file, every record reads back without error
+    client.shutdown().await.unwrap();
+    let records = capture_files(&dir)
+        .unwrap()
+        .iter()
+        .flat_map(|file| CaptureReader::open(file).unwrap())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records.len(), 4);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn persistence_in(dir: &Path) -> PersistenceConfig {
+    PersistenceConfig {
+        dir: dir.to_path_buf(),
+        interval: Duration::from_millis(50),
+        max_age: Duration::from_secs(60),
+        save_on_shutdown: true,
+    }
+}
+
+#[actix_web::test]
+async fn warm_starts_from_saved_books() {
+    let dir = std::env::temp_dir().join(format!("challenge-warm-start-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let saved = OrderBook::new(Pair::BTCUSDT, vec![level("100.00", "1.0")], vec![level("101.00", "1.0")], 100);
+    save_orderbook(&dir, &saved).unwrap();
+
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .session(vec![
+            Step::diff("BTCUSDT", 99, 102, &[("100.50", "2.0")], &[]),
+            Step::diff("BTCUSDT", 103, 104, &[], &[("100.80", "4.0")]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let client = client_with_persistence(&mock, vec![Pair::BTCUSDT], persistence_in(&dir));
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.50", "2.0"), ("100.80", "4.0")).await;
+    assert_eq!(mock.snapshot_requests(), 0);
+
+    // The live book is written back periodically
+    let expected = (level("100.50", "2.0"), level("100.80", "4.0"));
+    let mut persisted = false;
+    for _ in 0..100 {
+        let book = load_orderbook(&dir, Pair::BTCUSDT, Duration::from_secs(60)).unwrap().unwrap();
+        if book.get_tips().is_ok_and(|tips| tips == expected) {
+            persisted = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(persisted, "live book was never saved");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[actix_web::test]
+async fn falls_back_to_snapshot_when_saved_book_is_behind() {
+    let dir = std::env::temp_dir().join(format!("challenge-warm-start-stale-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let saved = OrderBook::new(Pair::BTCUSDT, vec![level("90.00", "1.0")], vec![level("91.00", "1.0")], 50);
+    save_orderbook(&dir, &saved).unwrap();
+
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .session(vec![
+            Step::diff("BTCUSDT", 101, 102, &[("100.50", "2.0")], &[]),
+            Step::WaitForSnapshots { count: 1 },
+            Step::diff("BTCUSDT", 103, 104, &[], &[("100.80", "4.0")]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let client = client_with_persistence(&mock, vec![Pair::BTCUSDT], persistence_in(&dir));
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.50", "2.0"), ("100.80", "4.0")).await;
+    assert_eq!(mock.snapshot_requests(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[actix_web::test]
+async fn records_top_of_book_history() {
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .session(vec![
+            // Held back until the book is live so every diff is a separate change
+            Step::WaitForRelease,
+            Step::diff("BTCUSDT", 101, 102, &[("100.50", "2.0")], &[]),
+            // Below the best bid, the top of book does not change
+            Step::diff("BTCUSDT", 103, 104, &[("99.00", "1.0")], &[]),
+            Step::diff("BTCUSDT", 105, 106, &[], &[("100.80", "4.0")]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let client = client_for(&mock, vec![Pair::BTCUSDT]);
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.00", "1.0"), ("101.00", "1.0")).await;
+    mock.release();
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.50", "2.0"), ("100.80", "4.0")).await;
+    let history = client.get_history(Pair::BTCUSDT, 0, u64::MAX).await.unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[0].mid, BigDecimal::from_str("100.5").unwrap());
+    assert_eq!(history[2].spread, BigDecimal::from_str("0.3").unwrap());
+    assert!(history.windows(2).all(|w| w[0].time <= w[1].time));
+
+    let last = history[2].time;
+    assert_eq!(client.get_history(Pair::BTCUSDT, last + 1, u64::MAX).await.unwrap(), vec![history[2].clone()]);
+}
+
+#[actix_web::test]
+async fn builds_candles_from_tip_changes() {
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .session(vec![
+            // Held back until the book is live so the diff is a separate change
+            Step::WaitForRelease,
+            Step::diff("BTCUSDT", 101, 102, &[("100.50", "3.0")], &[]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let client = client_for(&mock, vec![Pair::BTCUSDT]);
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.00", "1.0"), ("101.00", "1.0")).await;
+    mock.release();
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.50", "3.0"), ("101.00", "1.0")).await;
+    // Both changes usually land in one bar, unless they straddle a boundary
+    let candles = client.get_candles(Pair::BTCUSDT, Duration::from_secs(300), 10).await.unwrap();
+    let last = candles.last().unwrap();
+    assert_eq!(candles[0].mid.open, BigDecimal::from_str("100.5").unwrap());
+    assert_eq!(last.mid.close, BigDecimal::from_str("100.75").unwrap());
+    assert_eq!(last.microprice.close, BigDecimal::from_str("100.875").unwrap());
+    assert_eq!(candles.iter().map(|candle| candle.updates).sum::<u64>(), 2);
+
+    assert!(client.get_candles(Pair::BTCUSDT, Duration::from_secs(7), 10).await.is_err());
+}
+
+#[actix_web::test]
+async fn shuts_down_and_saves_final_books() {
+    let dir = std::env::temp_dir().join(format!("challenge-shutdown-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0")], &[("101.00", "1.0")])
+        .session(vec![
+            Step::WaitForSnapshots { count: 1 },
+            Step::diff("BTCUSDT", 101, 102, &[("100.50", "2.0")], &[]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    // Only the save on shutdown can write the live book
+    let persistence = PersistenceConfig {
+        interval: Duration::from_secs(3600),
+        ..persistence_in(&dir)
+    };
+    let (client, handle) = BinanceClient::new(BinanceConfig {
+        persistence: Some(persistence),
+        ..config_for(&mock, vec![Pair::BTCUSDT])
+    });
+
+    wait_for_tips(&client, Pair::BTCUSDT, ("100.50", "2.0"), ("101.00", "1.0")).await;
+    client.shutdown().await.unwrap();
+
+    assert!(handle.is_finished());
+    assert!(!client.is_connected());
+    assert_eq!(mock.close_frames(), 1);
+    let saved = load_orderbook(&dir, Pair::BTCUSDT, Duration::from_secs(60)).unwrap().unwrap();
+    assert_eq!(saved.last_update_id(), 102);
+    assert!(client.get_tips(Pair::BTCUSDT).await.is_err());
+    assert!(client.shutdown().await.is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,172 @@
+use std::{str::FromStr, time::Duration};
+
+use bigdecimal::BigDecimal;
+use challenge::{
+    binance::{BinanceClient, BinanceConfig, ReconnectPolicy, ReplayConfig, ReplaySpeed},
+    candles::CandleConfig,
+    capture::{capture_files, Capture, CaptureConfig, CaptureReader, RecordKind},
+    history::HistoryConfig,
+    mock::{MockBinance, Scenario, Step},
+    orderbook::{BookState, InvariantConfig, Pair, DEFAULT_STALE_AFTER},
+};
+
+fn captured_records(dir: &std::path::Path) -> usize {
+    capture_files(dir)
+        .unwrap()
+        .iter()
+        .flat_map(|file| CaptureReader::open(file).unwrap())
+        .filter(|record| record.is_ok())
+        .count()
+}
+
+#[actix_web::test]
+async fn replay_rebuilds_the_live_book() {
+    let dir = std::env::temp_dir().join(format!("challenge-replay-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let scenario = Scenario::default()
+        .snapshot("BTCUSDT", 100, &[("100.00", "1.0"), ("99.00", "2.0")], &[("101.00", "1.0")])
+        .snapshot("BTCUSDT", 110, &[("100.00", "3.0"), ("99.00", "2.0")], &[("101.00", "3.0")])
+        .snapshot("ETHUSDT", 500, &[("10.00", "1.0")], &[("11.00", "1.0")])
+        .session(vec![
+            Step::WaitForSnapshots { count: 2 },
+            Step::diff("BTCUSDT", 101, 102, &[("100.10", "1.0")], &[]),
+            Step::diff("ETHUSDT", 501, 503, &[], &[("10.90", "4.0")]),
+            // Gap, answered by the second BTCUSDT snapshot
+            Step::diff("BTCUSDT", 106, 108, &[("100.30", "1.0")], &[]),
+            Step::WaitForSnapshots { count: 3 },
+            Step::diff("BTCUSDT", 109, 112, &[("100.20", "2.0")], &[("101.00", "0")]),
+            Step::diff("BTCUSDT", 113, 114, &[], &[("102.00", "5.0")]),
+        ]);
+    let mock = MockBinance::start(scenario).await.unwrap();
+    let pairs = vec![Pair::BTCUSDT, Pair::ETHUSDT];
+    let config = BinanceConfig {
+        endpoints: mock.endpoints(),
+        pairs: pairs.clone(),
+        snapshot_depth: 1000,
+        reconnect: ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            max_attempts: Some(5),
+        },
+        capture: Some(CaptureConfig {
+            dir: dir.clone(),
+            max_file_bytes: 512,
+            max_file_age: Duration::from_secs(3600),
+        }),
+        persistence: None,
+        history: HistoryConfig::default(),
+        candles: CandleConfig::default(),
+        stale_after: DEFAULT_STALE_AFTER,
+        verification: None,
+        invariants: InvariantConfig::default(),
+    };
+    let (live, _handle) = BinanceClient::new(config.clone());
+
+    let expected_ask = (BigDecimal::from_str("102.00").unwrap(), BigDecimal::from_str("5.0").unwrap());
+    let mut synced = false;
+    for _ in 0..200 {
+        if live.get_tips(Pair::BTCUSDT).await.is_ok_and(|(_, ask)| ask == expected_ask) {
+            synced = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(synced, "live book never caught up with the scenario");
+
+    // One connection, three snapshots and five diffs
+    for _ in 0..100 {
+        if captured_records(&dir) == 9 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(captured_records(&dir), 9);
+
+    let (replayed, handle) = BinanceClient::replay(ReplayConfig {
+        source: dir.clone(),
+        speed: ReplaySpeed::AsFastAsPossible,
+        binance: BinanceConfig {
+            capture: None,
+            stale_after: Duration::from_millis(1),
+            ..config
+        },
+    })
+    .unwrap();
+    handle.await.unwrap();
+
+    for pair in pairs.iter() {
+        assert_eq!(replayed.get_bids(*pair).await.unwrap(), live.get_bids(*pair).await.unwrap());
+        assert_eq!(replayed.get_asks(*pair).await.unwrap(), live.get_asks(*pair).await.unwrap());
+    }
+
+    // History is stamped with the capture time of the records, not the time of the replay
+    let received_at = capture_files(&dir)
+        .unwrap()
+        .iter()
+        .flat_map(|file| CaptureReader::open(file).unwrap())
+        .filter_map(|record| record.ok().map(|record| record.received_at))
+        .collect::<Vec<u64>>();
+    let history = replayed.get_history(Pair::BTCUSDT, 0, u64::MAX).await.unwrap();
+    assert!(!history.is_empty());
+    assert!(history.iter().all(|entry| received_at.contains(&entry.time)));
+
+    // Books stay live once the replay is over
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let status = replayed.get_status().await.unwrap();
+    assert_eq!(status.iter().map(|status| status.state).collect::<Vec<BookState>>(), vec![BookState::Live; 2]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[actix_web::test]
+async fn finished_replay_stays_healthy() {
+    let dir = std::env::temp_dir().join(format!("challenge-replay-health-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let capture = Capture::start(CaptureConfig {
+        dir: dir.clone(),
+        max_file_bytes: 1024 * 1024,
+        max_file_age: Duration::from_secs(3600),
+    })
+    .unwrap();
+    capture.record(RecordKind::Connected, None, "ws://127.0.0.1/stream");
+    capture.record(RecordKind::Snapshot, Some("BTCUSDT"), r#"{"lastUpdateId":100,"bids":[["100.00","1.0"]],"asks":[["101.00","1.0"]]}"#);
+    capture.finish();
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_challenge"))
+        .env("CHALLENGE_PORT", port.to_string())
+        .env("CHALLENGE_SYMBOLS", "BTCUSDT")
+        .env("CHALLENGE_REPLAY", &dir)
+        .env("CHALLENGE_REPLAY_SPEED", "max")
+        .env("CHALLENGE_LOG_LEVEL", "error")
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Polls until the replay is over, the server keeps serving the replayed book
+    let url = format!("http://127.0.0.1:{}/health", port);
+    let mut last = None;
+    for _ in 0..200 {
+        if let Ok(response) = reqwest::get(&url).await {
+            let status = response.status();
+            let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+            if body["stream_running"] == false {
+                last = Some((status, body));
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    let (status, body) = last.expect("replay never finished");
+    assert_eq!(status, reqwest::StatusCode::OK);
+    assert_eq!(body["healthy"], true);
+    assert_eq!(body["replay"], true);
+    assert_eq!(body["books"][0]["state"], "live");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}